@@ -0,0 +1,413 @@
+/*!
+ * A small 6502 assembler for building test programs.
+ *
+ * Each line holds one instruction in the usual syntax (`LDA #$05`,
+ * `STA $0200,X`, `JMP ($FFFC)`, ...) or a data directive:
+ *
+ * - `.byte $01, $02` emits each value as a single byte.
+ * - `.word $1234` emits each value as two bytes, little-endian.
+ *
+ * Numbers are written in hex (`$FF`), binary (`%1010`) or decimal. Anything
+ * after a `;` is a comment.
+ *
+ * A line may start with a label (`loop:`), which can then be used anywhere a
+ * number is expected: as a JMP/JSR target, a branch target, or a `.word`.
+ * Labels are case-insensitive and always refer to a full 16-bit address.
+ * Assembly takes two passes, so a label can be used before it is defined.
+ */
+
+#[cfg(test)]
+#[path = "assembler_test.rs"]
+mod assembler_test;
+
+use crate::cpu::{AddressingMode, LoadError, CPU, NES_ROM_PROGRAM_START};
+use crate::opcodes;
+use std::collections::HashMap;
+use std::fmt;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum AssembleError {
+    UnknownMnemonic { line: usize, mnemonic: String },
+    UnknownDirective { line: usize, directive: String },
+    InvalidOperand { line: usize, operand: String },
+    BranchOutOfRange { line: usize, target: u16 },
+    UndefinedLabel { line: usize, label: String },
+    DuplicateLabel { line: usize, label: String },
+}
+
+impl fmt::Display for AssembleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AssembleError::UnknownMnemonic { line, mnemonic } => {
+                write!(f, "line {}: unknown instruction `{}`", line, mnemonic)
+            }
+            AssembleError::UnknownDirective { line, directive } => {
+                write!(f, "line {}: unknown directive `{}`", line, directive)
+            }
+            AssembleError::InvalidOperand { line, operand } => {
+                write!(f, "line {}: invalid operand `{}`", line, operand)
+            }
+            AssembleError::BranchOutOfRange { line, target } => {
+                write!(
+                    f,
+                    "line {}: branch target ${:04X} out of range",
+                    line, target
+                )
+            }
+            AssembleError::UndefinedLabel { line, label } => {
+                write!(f, "line {}: undefined label `{}`", line, label)
+            }
+            AssembleError::DuplicateLabel { line, label } => {
+                write!(f, "line {}: label `{}` is already defined", line, label)
+            }
+        }
+    }
+}
+
+impl std::error::Error for AssembleError {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum EvalError {
+    Assemble(AssembleError),
+    Load(LoadError),
+}
+
+impl fmt::Display for EvalError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EvalError::Assemble(err) => err.fmt(f),
+            EvalError::Load(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for EvalError {}
+
+impl From<AssembleError> for EvalError {
+    fn from(err: AssembleError) -> Self {
+        EvalError::Assemble(err)
+    }
+}
+
+impl From<LoadError> for EvalError {
+    fn from(err: LoadError) -> Self {
+        EvalError::Load(err)
+    }
+}
+
+impl CPU {
+    /**
+     * Assemble instructions and execute them in place.
+     *
+     * The code is written to memory at the program counter and executed
+     * until the program counter leaves it or the CPU halts, leaving the
+     * registers updated. This makes it easy to drive the CPU interactively:
+     * `cpu.eval("LDA #$05")?; cpu.eval("TAX")?;`.
+     *
+     * @param asm One or more lines of assembly.
+     */
+    pub fn eval(&mut self, asm: &str) -> Result<(), EvalError> {
+        let start = self.program_counter;
+        let code = assemble_at(start, asm)?;
+        self.load_memory(start, &code)?;
+
+        // Code may end at $FFFF, so the end of the range needs more than
+        // 16 bits.
+        let range = start as u32..start as u32 + code.len() as u32;
+        self.run_until(|cpu| !range.contains(&(cpu.program_counter as u32)));
+        Ok(())
+    }
+}
+
+/**
+ * Assemble a program to be loaded at the usual program start, `$8000`.
+ *
+ * @param source The program text.
+ * @return The assembled machine code.
+ */
+pub fn assemble(source: &str) -> Result<Vec<u8>, AssembleError> {
+    assemble_at(NES_ROM_PROGRAM_START, source)
+}
+
+/**
+ * Assemble a program to be loaded at a given address.
+ *
+ * The origin is needed to resolve labels and branch targets.
+ *
+ * @param origin The address the first assembled byte will be loaded at.
+ * @param source The program text.
+ * @return The assembled machine code.
+ */
+pub fn assemble_at(origin: u16, source: &str) -> Result<Vec<u8>, AssembleError> {
+    // The first pass only collects label addresses. Forward references are
+    // not known yet, but since labels are always encoded as full words the
+    // size of every line, and so every label address, is already final.
+    let mut first = Assembler {
+        origin,
+        labels: HashMap::new(),
+        resolve_labels: false,
+    };
+    first.pass(source)?;
+
+    let mut second = Assembler {
+        origin,
+        labels: first.labels,
+        resolve_labels: true,
+    };
+    second.pass(source)
+}
+
+struct Assembler {
+    origin: u16,
+    labels: HashMap<String, u16>,
+    resolve_labels: bool,
+}
+
+impl Assembler {
+    /**
+     * Assemble every line of the program once.
+     */
+    fn pass(&mut self, source: &str) -> Result<Vec<u8>, AssembleError> {
+        let mut output = Vec::new();
+
+        for (index, raw) in source.lines().enumerate() {
+            let line = index + 1;
+            let mut text = match raw.find(';') {
+                Some(comment) => &raw[..comment],
+                None => raw,
+            }
+            .trim();
+
+            let pc = self.origin.wrapping_add(output.len() as u16);
+            if let Some(colon) = text.find(':') {
+                let label = text[..colon].trim();
+                if !is_identifier(label) {
+                    return Err(invalid_operand(line, label));
+                }
+                if !self.resolve_labels
+                    && self.labels.insert(label.to_ascii_uppercase(), pc).is_some()
+                {
+                    return Err(AssembleError::DuplicateLabel {
+                        line,
+                        label: label.to_string(),
+                    });
+                }
+                text = text[colon + 1..].trim();
+            }
+            if text.is_empty() {
+                continue;
+            }
+
+            let (head, operand) = match text.find(char::is_whitespace) {
+                Some(split) => (&text[..split], text[split..].trim()),
+                None => (text, ""),
+            };
+
+            if head.starts_with('.') {
+                self.directive(line, head, operand, &mut output)?;
+            } else {
+                self.instruction(line, pc, head, operand, &mut output)?;
+            }
+        }
+
+        Ok(output)
+    }
+
+    /**
+     * Emit the bytes for a data directive.
+     */
+    fn directive(
+        &self,
+        line: usize,
+        directive: &str,
+        operand: &str,
+        output: &mut Vec<u8>,
+    ) -> Result<(), AssembleError> {
+        let values = operand
+            .split(',')
+            .map(|value| self.value(line, &value.trim().to_ascii_uppercase()));
+
+        match directive.to_ascii_lowercase().as_str() {
+            ".byte" => {
+                for value in values {
+                    let (value, _) = value?;
+                    if value > 0xFF {
+                        return Err(invalid_operand(line, operand));
+                    }
+                    output.push(value as u8);
+                }
+            }
+            ".word" => {
+                for value in values {
+                    let (value, _) = value?;
+                    output.extend_from_slice(&value.to_le_bytes());
+                }
+            }
+            _ => {
+                return Err(AssembleError::UnknownDirective {
+                    line,
+                    directive: directive.to_string(),
+                })
+            }
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Emit the opcode and operand bytes for a single instruction.
+     */
+    fn instruction(
+        &self,
+        line: usize,
+        pc: u16,
+        mnemonic: &str,
+        operand: &str,
+        output: &mut Vec<u8>,
+    ) -> Result<(), AssembleError> {
+        let mnemonic = mnemonic.to_ascii_uppercase();
+        if !opcodes::CPU_OPCODES
+            .iter()
+            .any(|op| op.instruction == mnemonic)
+        {
+            return Err(AssembleError::UnknownMnemonic { line, mnemonic });
+        }
+
+        let find = |mode: AddressingMode| {
+            opcodes::CPU_OPCODES
+                .iter()
+                .find(|op| op.instruction == mnemonic && op.mode == mode)
+        };
+        let invalid = || invalid_operand(line, operand);
+        let upper = operand.to_ascii_uppercase().replace(' ', "");
+
+        // Implied and accumulator instructions have no operand bytes, but BRK
+        // is followed by a padding byte that its length counts.
+        if upper.is_empty() || upper == "A" {
+            let op = find(AddressingMode::NoneAddressing)
+                .or_else(|| find(AddressingMode::Accumulator))
+                .ok_or_else(invalid)?;
+            output.push(op.code);
+            output.resize(output.len() + op.length as usize - 1, 0x00);
+            return Ok(());
+        }
+
+        // Branches encode their target as an offset from the next
+        // instruction. Until labels are resolved the offset is a placeholder.
+        if let Some(op) = find(AddressingMode::Relative) {
+            let (target, _) = self.value(line, &upper)?;
+            let offset = target.wrapping_sub(pc.wrapping_add(2)) as i16;
+            if self.resolve_labels && !(-128..=127).contains(&offset) {
+                return Err(AssembleError::BranchOutOfRange { line, target });
+            }
+            output.push(op.code);
+            output.push(offset as u8);
+            return Ok(());
+        }
+
+        let (value, wide, modes) = if let Some(value) = upper.strip_prefix('#') {
+            let (value, _) = self.value(line, value)?;
+            (value, false, [AddressingMode::Immediate; 2])
+        } else if let Some(inner) = upper.strip_prefix('(') {
+            let (inner, mode) = if let Some(inner) = inner.strip_suffix(",X)") {
+                (inner, AddressingMode::IndirectX)
+            } else if let Some(inner) = inner.strip_suffix("),Y") {
+                (inner, AddressingMode::IndirectY)
+            } else if let Some(inner) = inner.strip_suffix(')') {
+                (inner, AddressingMode::Indirect)
+            } else {
+                return Err(invalid());
+            };
+            let (value, _) = self.value(line, inner)?;
+            (value, mode == AddressingMode::Indirect, [mode; 2])
+        } else if let Some(address) = upper.strip_suffix(",X") {
+            let (value, wide) = self.value(line, address)?;
+            let modes = [AddressingMode::ZeroPageX, AddressingMode::AbsoluteX];
+            (value, wide, modes)
+        } else if let Some(address) = upper.strip_suffix(",Y") {
+            let (value, wide) = self.value(line, address)?;
+            let modes = [AddressingMode::ZeroPageY, AddressingMode::AbsoluteY];
+            (value, wide, modes)
+        } else {
+            let (value, wide) = self.value(line, &upper)?;
+            let modes = [AddressingMode::ZeroPage, AddressingMode::Absolute];
+            (value, wide, modes)
+        };
+
+        // Prefer the shorter zero page form when the address allows it,
+        // falling back to the absolute form when the instruction has no zero
+        // page form.
+        let op = if wide {
+            find(modes[1])
+        } else {
+            find(modes[0]).or_else(|| find(modes[1]))
+        }
+        .ok_or_else(invalid)?;
+
+        output.push(op.code);
+        match op.length {
+            1 => {}
+            2 if value <= 0xFF => output.push(value as u8),
+            3 => output.extend_from_slice(&value.to_le_bytes()),
+            _ => return Err(invalid()),
+        }
+
+        Ok(())
+    }
+
+    /**
+     * Evaluate a number or a label.
+     *
+     * During the first pass, labels that are not defined yet evaluate to
+     * zero.
+     *
+     * @return The value, and whether it must be encoded as a full word.
+     */
+    fn value(&self, line: usize, text: &str) -> Result<(u16, bool), AssembleError> {
+        if is_identifier(text) {
+            return match self.labels.get(text) {
+                Some(&addr) => Ok((addr, true)),
+                None if !self.resolve_labels => Ok((0, true)),
+                None => Err(AssembleError::UndefinedLabel {
+                    line,
+                    label: text.to_string(),
+                }),
+            };
+        }
+        parse_number(text).ok_or_else(|| invalid_operand(line, text))
+    }
+}
+
+/**
+ * Parse a hex (`$`), binary (`%`) or decimal number.
+ *
+ * @return The value, and whether it must be encoded as a full word, either
+ *         because it is too large for a byte or because it was written with
+ *         more than two hex digits (e.g. `$0010`).
+ */
+fn parse_number(text: &str) -> Option<(u16, bool)> {
+    let (value, wide) = if let Some(hex) = text.strip_prefix('$') {
+        (u16::from_str_radix(hex, 16).ok()?, hex.len() > 2)
+    } else if let Some(binary) = text.strip_prefix('%') {
+        (u16::from_str_radix(binary, 2).ok()?, false)
+    } else {
+        (text.parse::<u16>().ok()?, false)
+    };
+    Some((value, wide || value > 0xFF))
+}
+
+/**
+ * Whether some text is a valid label name.
+ */
+fn is_identifier(text: &str) -> bool {
+    let mut chars = text.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn invalid_operand(line: usize, operand: &str) -> AssembleError {
+    AssembleError::InvalidOperand {
+        line,
+        operand: operand.trim().to_string(),
+    }
+}
@@ -0,0 +1,228 @@
+/**
+ * Unit tests for the assembler.
+ */
+use super::*;
+use crate::cpu::CPU;
+
+#[test]
+fn test_assemble_addressing_modes() {
+    let program = assemble(
+        "
+        LDA #$05      ; immediate
+        LDA $10
+        LDA $10,X
+        LDX $10,Y
+        LDA $0010
+        LDA $1234,X
+        LDA $1234,Y
+        LDA ($20,X)
+        LDA ($20),Y
+        JMP ($FFFC)
+        JMP $8000
+        TAX
+        ",
+    )
+    .unwrap();
+
+    assert_eq!(
+        program,
+        vec![
+            0xA9, 0x05, 0xA5, 0x10, 0xB5, 0x10, 0xB6, 0x10, 0xAD, 0x10, 0x00, 0xBD, 0x34, 0x12,
+            0xB9, 0x34, 0x12, 0xA1, 0x20, 0xB1, 0x20, 0x6C, 0xFC, 0xFF, 0x4C, 0x00, 0x80, 0xAA,
+        ]
+    );
+}
+
+#[test]
+fn test_assemble_zero_page_falls_back_to_absolute() {
+    // There is no zero page form of LDA $nn,Y.
+    assert_eq!(assemble("LDA $10,Y").unwrap(), vec![0xB9, 0x10, 0x00]);
+}
+
+#[test]
+fn test_assemble_brk_includes_padding_byte() {
+    assert_eq!(assemble("BRK\nTAX").unwrap(), vec![0x00, 0x00, 0xAA]);
+}
+
+#[test]
+fn test_assemble_branch_to_address() {
+    assert_eq!(
+        assemble("LDX #$01\nBNE $8000").unwrap(),
+        vec![0xA2, 0x01, 0xD0, 0xFC]
+    );
+    assert_eq!(
+        assemble("BNE $9000"),
+        Err(AssembleError::BranchOutOfRange {
+            line: 1,
+            target: 0x9000
+        })
+    );
+}
+
+#[test]
+fn test_assemble_data_directives() {
+    let program = assemble(
+        "
+        LDX #$01
+        LDA $8007,X
+        BRK
+        .byte $11, $22, 51
+        .word $1234, %1
+        ",
+    )
+    .unwrap();
+
+    assert_eq!(
+        program,
+        vec![0xA2, 0x01, 0xBD, 0x07, 0x80, 0x00, 0x00, 0x11, 0x22, 0x33, 0x34, 0x12, 0x01, 0x00]
+    );
+
+    let mut cpu = CPU::new();
+    cpu.run(program);
+    assert_eq!(cpu.register_a, 0x22);
+    assert_eq!(cpu.peek_u16(0x800A), 0x1234);
+}
+
+#[test]
+fn test_assemble_errors() {
+    assert_eq!(
+        assemble("FOO"),
+        Err(AssembleError::UnknownMnemonic {
+            line: 1,
+            mnemonic: String::from("FOO")
+        })
+    );
+    assert_eq!(
+        assemble("TAX\nLDA #$100"),
+        Err(AssembleError::InvalidOperand {
+            line: 2,
+            operand: String::from("#$100")
+        })
+    );
+    assert_eq!(
+        assemble(".byte $100"),
+        Err(AssembleError::InvalidOperand {
+            line: 1,
+            operand: String::from("$100")
+        })
+    );
+    assert_eq!(
+        assemble(".ascii \"HI\""),
+        Err(AssembleError::UnknownDirective {
+            line: 1,
+            directive: String::from(".ascii")
+        })
+    );
+}
+
+#[test]
+fn test_assemble_counting_loop_with_labels() {
+    let program = assemble(
+        "
+                LDX #$00
+                LDY #$00
+        loop:   INX
+                CPX #$05
+                BNE loop
+                BRK
+        ",
+    )
+    .unwrap();
+    assert_eq!(
+        program,
+        vec![0xA2, 0x00, 0xA0, 0x00, 0xE8, 0xE0, 0x05, 0xD0, 0xFB, 0x00, 0x00]
+    );
+
+    let mut cpu = CPU::new();
+    cpu.run(program);
+    assert_eq!(cpu.register_x, 5);
+}
+
+#[test]
+fn test_assemble_forward_label_references() {
+    let program = assemble(
+        "
+                JMP start
+        table:  .byte $AA, $BB
+        start:  LDA table
+                BRK
+        ptr:    .word start
+        ",
+    )
+    .unwrap();
+    assert_eq!(
+        program,
+        vec![0x4C, 0x05, 0x80, 0xAA, 0xBB, 0xAD, 0x03, 0x80, 0x00, 0x00, 0x05, 0x80]
+    );
+
+    let mut cpu = CPU::new();
+    cpu.run(program);
+    assert_eq!(cpu.register_a, 0xAA);
+}
+
+#[test]
+fn test_assemble_label_errors() {
+    assert_eq!(
+        assemble("JMP nowhere"),
+        Err(AssembleError::UndefinedLabel {
+            line: 1,
+            label: String::from("NOWHERE")
+        })
+    );
+    assert_eq!(
+        assemble("here: TAX\nhere: TAX"),
+        Err(AssembleError::DuplicateLabel {
+            line: 2,
+            label: String::from("here")
+        })
+    );
+}
+
+#[test]
+fn test_eval_updates_cpu_in_place() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![]);
+    cpu.reset();
+
+    cpu.eval("LDA #$05").unwrap();
+    assert_eq!(cpu.register_a, 0x05);
+    assert_eq!(cpu.program_counter, 0x8002);
+
+    cpu.eval("TAX").unwrap();
+    assert_eq!(cpu.register_x, 0x05);
+    assert_eq!(cpu.program_counter, 0x8003);
+
+    cpu.eval("loop: INX\nCPX #$08\nBNE loop").unwrap();
+    assert_eq!(cpu.register_x, 0x08);
+}
+
+#[test]
+fn test_eval_reports_errors() {
+    let mut cpu = CPU::new();
+    assert_eq!(
+        cpu.eval("LDA"),
+        Err(EvalError::Assemble(AssembleError::InvalidOperand {
+            line: 1,
+            operand: String::new()
+        }))
+    );
+
+    cpu.program_counter = 0xFFFE;
+    assert_eq!(
+        cpu.eval("LDA $1234"),
+        Err(EvalError::Load(LoadError::DoesNotFit {
+            addr: 0xFFFE,
+            len: 3
+        }))
+    );
+}
+
+#[test]
+fn test_eval_at_end_of_memory() {
+    let mut cpu = CPU::new();
+    cpu.program_counter = 0xFFFE;
+    cpu.eval("LDA #$05").unwrap();
+    assert_eq!(cpu.register_a, 0x05);
+    assert_eq!(cpu.mem_read(0xFFFF), 0x05);
+    assert_eq!(cpu.program_counter, 0x0000);
+}
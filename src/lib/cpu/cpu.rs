@@ -1,4 +1,4 @@
-/**
+/*!
  * Library for emulating a 6502 CPU.
  */
 
@@ -7,10 +7,23 @@
 mod cpu_test;
 
 use crate::opcodes;
-use std::collections::HashMap;
+#[cfg(any(test, feature = "testing"))]
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+
+const NES_MAX_MEMORY: usize = 0x10000; // 64 KiB
+pub(crate) const NES_ROM_PROGRAM_START: u16 = 0x8000;
+const NES_RAM_SIZE: usize = 0x0800; // 2 KiB internal work RAM
 
-const NES_MAX_MEMORY: usize = 0xFFFF; // 64 KiB
-const NES_ROM_PROGRAM_START: usize = 0x8000;
+// The stack lives in page one and grows downward.
+pub(crate) const STACK: u16 = 0x0100;
+const STACK_RESET: u8 = 0xFD;
+
+// The interrupt vectors at the top of memory.
+const NMI_VECTOR: u16 = 0xFFFA;
+const RESET_VECTOR: u16 = 0xFFFC;
+const IRQ_VECTOR: u16 = 0xFFFE;
 
 // Status flags for the CPU Processor Status register.
 const STATUS_CARRY: u8 = 0b0000_0001;
@@ -18,11 +31,12 @@ const STATUS_ZERO: u8 = 0b0000_0010;
 const STATUS_INTERRUPT_DISABLE: u8 = 0b0000_0100;
 const STATUS_DECIMAL_MODE: u8 = 0b0000_1000;
 const STATUS_BREAK: u8 = 0b0001_0000;
-// No status flag set here
+// Unused; always reads back as set on hardware.
+const STATUS_UNUSED: u8 = 0b0010_0000;
 const STATUS_OVERFLOW: u8 = 0b0100_0000;
 const STATUS_NEGATIVE: u8 = 0b1000_0000;
 
-#[derive(Debug)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum AddressingMode {
     Accumulator,
     Immediate,
@@ -35,6 +49,7 @@ pub enum AddressingMode {
     Indirect,
     IndirectX,
     IndirectY,
+    Relative,
     NoneAddressing,
 }
 
@@ -43,8 +58,337 @@ pub struct CPU {
     pub register_x: u8,
     pub register_y: u8,
     pub status: u8,
+    pub stack_pointer: u8,
     pub program_counter: u16,
+    pub cycles: u64,
     memory: [u8; NES_MAX_MEMORY],
+    halted: bool,
+    jammed: bool,
+    unknown_opcode: Option<u8>,
+    halt_on_jump_to_self: bool,
+    extra_cycles: u8,
+    stack_debug: bool,
+    stack_wraps: Vec<StackWrap>,
+    instruction_pc: u16,
+    write_log_capacity: usize,
+    write_log: Vec<WriteEvent>,
+    cycle_overrides: HashMap<u8, u8>,
+    undo_depth: usize,
+    undo_ring: VecDeque<UndoEntry>,
+    undo_recording: bool,
+    breakpoints: HashMap<u16, Breakpoint>,
+    patches: HashMap<u16, u8>,
+    last_instruction_cycles: u8,
+    #[cfg(feature = "illegal_opcodes")]
+    unstable_magic: Option<u8>,
+    #[cfg(feature = "illegal_opcodes")]
+    unstable_rng: u64,
+    serial_port: Option<u16>,
+    serial_output: String,
+    illegal_opcode_policy: IllegalOpcodePolicy,
+    skipped_opcodes: Vec<(u16, u8)>,
+    #[cfg(any(test, feature = "testing"))]
+    access_tracer: RefCell<Option<Box<AccessTracer>>>,
+}
+
+#[cfg(any(test, feature = "testing"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    Read,
+    Write,
+}
+
+/**
+ * A single memory access, as reported to an access tracer.
+ *
+ * `pc` is the address of the instruction that made the access.
+ */
+#[cfg(any(test, feature = "testing"))]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub pc: u16,
+    pub addr: u16,
+    pub value: u8,
+    pub kind: AccessKind,
+}
+
+#[cfg(any(test, feature = "testing"))]
+type AccessTracer = dyn FnMut(&MemoryAccess);
+
+/**
+ * A write to memory, as recorded by the write log.
+ *
+ * `pc` is the address of the instruction that performed the write.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WriteEvent {
+    pub pc: u16,
+    pub addr: u16,
+    pub value: u8,
+}
+
+/**
+ * A breakpoint, which stops `CPU::run_to_breakpoint()` once the instruction
+ * at its address has been reached `break_after` times.
+ */
+struct Breakpoint {
+    hits: u32,
+    break_after: u32,
+}
+
+/**
+ * What is needed to undo one instruction: the state before it executed and
+ * the previous value of each byte it wrote, in the order written.
+ */
+struct UndoEntry {
+    state: CpuState,
+    halted: bool,
+    jammed: bool,
+    writes: Vec<(u16, u8)>,
+}
+
+/**
+ * How the unstable unofficial opcodes ANE and LXA behave.
+ *
+ * On hardware their result depends on analog effects, modelled as a "magic"
+ * constant ORed into the accumulator. `Magic` uses a fixed constant, while
+ * `Seeded` draws a new constant for each instruction from a generator, so
+ * that behavior varies but is still reproducible.
+ */
+#[cfg(feature = "illegal_opcodes")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnstableOpcodes {
+    Magic(u8),
+    Seeded(u64),
+}
+
+/**
+ * Errors from copying data directly into memory.
+ */
+#[derive(Debug, PartialEq, Eq)]
+pub enum LoadError {
+    DoesNotFit { addr: u16, len: usize },
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LoadError::DoesNotFit { addr, len } => {
+                write!(f, "{} bytes do not fit in memory at ${:04X}", len, addr)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LoadError {}
+
+/**
+ * Why a run of the CPU stopped.
+ *
+ * `Halted` is a clean exit through BRK (or a jump to self, when enabled),
+ * while `Jammed` means a JAM opcode locked up the CPU, which usually
+ * indicates a crash. `UnknownOpcode` means the run reached an opcode the CPU
+ * cannot execute under `IllegalOpcodePolicy::Panic`, and stopped with the
+ * program counter on it. `BudgetExhausted` is returned by the limited runs
+ * when the instruction limit is reached first.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunResult {
+    Halted,
+    Jammed,
+    UnknownOpcode(u8),
+    BreakpointHit(u16),
+    BudgetExhausted,
+}
+
+/**
+ * What the CPU does on reaching an opcode it cannot execute.
+ *
+ * `Panic` aborts, which is the default so that gaps in the instruction set
+ * are noticed. The runs stop and return `RunResult::UnknownOpcode` instead,
+ * so only `step()` itself panics. `Nop` records the opcode and skips it, so that partially
+ * supported programs keep running during development.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IllegalOpcodePolicy {
+    Panic,
+    Nop,
+}
+
+/**
+ * A push or pull that wrapped the stack pointer around page one.
+ *
+ * `pc` is the address of the instruction that caused the wrap. Recorded
+ * when `CPU::set_stack_debug()` is enabled.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackWrap {
+    Overflow { pc: u16 },
+    Underflow { pc: u16 },
+}
+
+/**
+ * The processor status flags, decoded into booleans.
+ *
+ * Returned by `CPU::flags()`.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Flags {
+    pub carry: bool,
+    pub zero: bool,
+    pub interrupt_disable: bool,
+    pub decimal: bool,
+    pub break_flag: bool,
+    pub overflow: bool,
+    pub negative: bool,
+}
+
+/**
+ * A single processor status flag, for use with `CPU::is_flag_set()`.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flag {
+    Carry,
+    Zero,
+    InterruptDisable,
+    Decimal,
+    Break,
+    Overflow,
+    Negative,
+}
+
+impl Flag {
+    /**
+     * The bit of the status register holding this flag.
+     */
+    pub fn mask(self) -> u8 {
+        match self {
+            Flag::Carry => STATUS_CARRY,
+            Flag::Zero => STATUS_ZERO,
+            Flag::InterruptDisable => STATUS_INTERRUPT_DISABLE,
+            Flag::Decimal => STATUS_DECIMAL_MODE,
+            Flag::Break => STATUS_BREAK,
+            Flag::Overflow => STATUS_OVERFLOW,
+            Flag::Negative => STATUS_NEGATIVE,
+        }
+    }
+}
+
+/**
+ * The CPU registers and cycle count, without memory.
+ *
+ * Captured by `CPU::state()` and applied with `CPU::restore()`.
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CpuState {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub sp: u8,
+    pub pc: u16,
+    pub status: u8,
+    pub cycles: u64,
+}
+
+/**
+ * Summary of a single executed instruction.
+ *
+ * Register values are those left behind once the instruction completed.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StepInfo {
+    pub pc: u16,
+    pub opcode: u8,
+    pub register_a: u8,
+    pub register_x: u8,
+    pub register_y: u8,
+    pub status: u8,
+    pub stack_pointer: u8,
+    pub cycles: u8,
+}
+
+/**
+ * Iterator executing one instruction per call to `next()`.
+ *
+ * Created by `CPU::instructions()`. The stream ends once the CPU halts.
+ */
+pub struct InstructionStream<'a> {
+    cpu: &'a mut CPU,
+}
+
+impl Iterator for InstructionStream<'_> {
+    type Item = StepInfo;
+
+    fn next(&mut self) -> Option<StepInfo> {
+        if self.cpu.halted {
+            return None;
+        }
+
+        let pc = self.cpu.program_counter;
+        let opcode = self.cpu.peek(pc);
+        if !self.cpu.run_step() {
+            return None;
+        }
+        let cycles = self.cpu.last_instruction_cycles();
+        Some(StepInfo {
+            pc,
+            opcode,
+            register_a: self.cpu.register_a,
+            register_x: self.cpu.register_x,
+            register_y: self.cpu.register_y,
+            status: self.cpu.status,
+            stack_pointer: self.cpu.stack_pointer,
+            cycles,
+        })
+    }
+}
+
+/**
+ * A copy of system memory, taken to find out what a program later changes.
+ *
+ * Created by `CPU::snapshot_memory()`.
+ */
+#[cfg(any(test, feature = "testing"))]
+pub struct MemorySnapshot {
+    memory: Vec<u8>,
+}
+
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/**
+ * Whether two addresses lie on different pages of memory.
+ *
+ * Indexing wraps from the top of memory to the bottom, so an address at
+ * `$00xx` reached from a base at `$FFxx` is on a different page.
+ */
+fn page_crossed(base: u16, addr: u16) -> bool {
+    base & 0xFF00 != addr & 0xFF00
+}
+
+/**
+ * Turn a seed into a state for `rng_next()`.
+ */
+fn rng_state(seed: u64) -> u64 {
+    // xorshift64* never leaves the zero state, so nudge a zero seed.
+    if seed == 0 {
+        0x9E37_79B9_7F4A_7C15
+    } else {
+        seed
+    }
+}
+
+/**
+ * Generate a pseudo-random byte with xorshift64*.
+ */
+fn rng_next(state: &mut u64) -> u8 {
+    *state ^= *state >> 12;
+    *state ^= *state << 25;
+    *state ^= *state >> 27;
+    (state.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
 }
 
 impl CPU {
@@ -54,8 +398,57 @@ impl CPU {
             register_x: 0,
             register_y: 0,
             status: 0,
+            stack_pointer: STACK_RESET,
             program_counter: 0,
+            cycles: 0,
             memory: [0; NES_MAX_MEMORY],
+            halted: false,
+            jammed: false,
+            unknown_opcode: None,
+            halt_on_jump_to_self: false,
+            extra_cycles: 0,
+            stack_debug: false,
+            stack_wraps: Vec::new(),
+            instruction_pc: 0,
+            write_log_capacity: 0,
+            write_log: Vec::new(),
+            cycle_overrides: HashMap::new(),
+            undo_depth: 0,
+            undo_ring: VecDeque::new(),
+            undo_recording: false,
+            breakpoints: HashMap::new(),
+            patches: HashMap::new(),
+            last_instruction_cycles: 0,
+            #[cfg(feature = "illegal_opcodes")]
+            unstable_magic: Some(0xEE),
+            #[cfg(feature = "illegal_opcodes")]
+            unstable_rng: 0,
+            serial_port: None,
+            serial_output: String::new(),
+            illegal_opcode_policy: IllegalOpcodePolicy::Panic,
+            skipped_opcodes: Vec::new(),
+            #[cfg(any(test, feature = "testing"))]
+            access_tracer: RefCell::new(None),
+        }
+    }
+
+    /**
+     * Fill the internal work RAM with deterministic pseudo-random bytes.
+     *
+     * A freshly constructed CPU applies an all-zeroes power-on fill. Real
+     * hardware powers on with indeterminate RAM contents, and some games
+     * derive randomness from it. Seeding the 2 KiB of work RAM
+     * (`$0000`-`$07FF`) from a fixed seed reproduces that behavior while
+     * keeping runs repeatable. Call this before `reset()`; neither `load()`
+     * nor `reset()` touch work RAM.
+     *
+     * @param seed The seed for the generator. The same seed always produces
+     *             the same RAM contents.
+     */
+    pub fn seed_ram(&mut self, seed: u64) {
+        let mut state = rng_state(seed);
+        for byte in self.memory[..NES_RAM_SIZE].iter_mut() {
+            *byte = rng_next(&mut state);
         }
     }
 
@@ -64,258 +457,1799 @@ impl CPU {
      *
      * @param addr The address of memory from which to read.
      */
-    fn mem_read(&self, addr: u16) -> u8 {
-        self.memory[addr as usize]
+    pub(crate) fn mem_read(&self, addr: u16) -> u8 {
+        let value = self.peek(addr);
+        #[cfg(any(test, feature = "testing"))]
+        self.trace_access(addr, value, AccessKind::Read);
+        value
     }
 
     /**
-     * Read a word from memory.
+     * Read a byte from memory without any of the side effects of a read.
      *
-     * This function reads data from memory packed in little-endian format.
+     * Used by debugging tools, such as the tracer, that must observe memory
+     * without disturbing it.
+     *
+     * @param addr The address of memory from which to read.
+     */
+    pub(crate) fn peek(&self, addr: u16) -> u8 {
+        if let Some(&value) = self.patches.get(&addr) {
+            return value;
+        }
+        self.memory[addr as usize]
+    }
+
+    /**
+     * Read a word from memory without any of the side effects of a read.
      *
      * @param pos Position in memory from which to read.
      * @return The word at that position.
      */
-    fn mem_read_u16(&self, pos: u16) -> u16 {
-        let lower = self.mem_read(pos);
-        let upper = self.mem_read(pos + 1);
-        u16::from_le_bytes([lower, upper])
+    pub(crate) fn peek_u16(&self, pos: u16) -> u16 {
+        u16::from_le_bytes([self.peek(pos), self.peek(pos.wrapping_add(1))])
     }
 
     /**
-     * Write a byte to a location in memory.
+     * Read a word from the zero page, wrapping within it, without any of
+     * the side effects of a read.
      *
-     * @param addr The address of memory to which to write.
-     * @param data The byte to write to the address.
+     * The indexed indirect modes read their pointer this way: a pointer at
+     * `$FF` takes its upper byte from `$00`, where `peek_u16()` would read
+     * it from `$0100`.
+     *
+     * @param zp The zero page address of the lower byte.
+     * @return The word at that position.
      */
-    fn mem_write(&mut self, addr: u16, data: u8) {
-        self.memory[addr as usize] = data;
+    pub(crate) fn peek_u16_zp_wrap(&self, zp: u8) -> u16 {
+        self.zp_u16(zp, CPU::peek)
     }
 
     /**
-     * Write a word to a location in memory.
+     * Common implementation of zero page word reads.
+     */
+    fn zp_u16(&self, zp: u8, read: fn(&CPU, u16) -> u8) -> u16 {
+        u16::from_le_bytes([read(self, zp as u16), read(self, zp.wrapping_add(1) as u16)])
+    }
+
+    /**
+     * Read one of the interrupt vectors at the top of memory.
      *
-     * This function writes data to memory, packed in little-endian format.
+     * Vectors are fetched without read side effects, so that a vector
+     * fetch can never disturb an I/O register.
      *
-     * @param pos The position in memory to which to write.
-     * @param data The word to write to the address.
+     * @param vector The address of the vector, e.g. `RESET_VECTOR`.
+     * @return The address the vector points to.
      */
-    fn mem_write_u16(&mut self, pos: u16, data: u16) {
-        let bytes = data.to_le_bytes();
-        let lower = bytes[0];
-        let upper = bytes[1];
-        self.mem_write(pos, lower);
-        self.mem_write(pos + 1, upper);
+    fn read_vector(&self, vector: u16) -> u16 {
+        self.peek_u16(vector)
     }
 
     /**
-     * Determine the memory address of the argument pointed to by the PRG CTR.
+     * Write a byte to a location in memory.
      *
-     * @param mode The type of addressing mode to use.
-     * @return The memory address from which we can locate a value.
+     * @param addr The address of memory to which to write.
+     * @param data The byte to write to the address.
      */
-    fn get_operand_address(&self, mode: &AddressingMode) -> u16 {
-        match mode {
-            // Immediate addressing does not rely on a memory address and loads
-            // the value into the register immediately. When a program is
-            // running, the immediate value to load is that which is pointed at
-            // by the program counter in memory.
-            AddressingMode::Immediate => self.program_counter,
-
-            // Absolute addressing uses the full memory location to locate
-            // a value.
-            AddressingMode::Absolute => self.mem_read_u16(self.program_counter),
-
-            // Like Absolute addressing, but the value of Register X is added
-            // to determine the final address.
-            AddressingMode::AbsoluteX => {
-                let pos = self.mem_read_u16(self.program_counter);
-                let addr = pos.wrapping_add(self.register_x as u16);
-                addr
-            }
-
-            // Like Absolute addressing, but the value of Register Y is added
-            // to determine the final address.
-            AddressingMode::AbsoluteY => {
-                let pos = self.mem_read_u16(self.program_counter);
-                let addr = pos.wrapping_add(self.register_y as u16);
-                addr
-            }
-
-            // Zero Page addressing only reads from the first page of memory.
-            // Think: Zero-indexing. This means the address we need to read
-            // is at 0x00nn. Functions the same as Absolute addressing.
-            AddressingMode::ZeroPage => self.mem_read(self.program_counter) as u16,
-
-            // Like Zero Page addressing, but the value of Register X is added
-            // to determine the final address.
-            AddressingMode::ZeroPageX => {
-                let pos = self.mem_read(self.program_counter);
-                let addr = pos.wrapping_add(self.register_x) as u16;
-                addr
-            }
-
-            // Like Zero Page addressing, but the value of Register Y is added
-            // to determine the final address.
-            AddressingMode::ZeroPageY => {
-                let pos = self.mem_read(self.program_counter);
-                let addr = pos.wrapping_add(self.register_y) as u16;
-                addr
-            }
-
-            // With Indirect addressing, the memory address that the PRG CTR
-            // points to is itself pointing at another memory address. To
-            // determine the final address, we dereference twice.
-            AddressingMode::Indirect => {
-                let pos = self.mem_read_u16(self.program_counter);
-                let addr = self.mem_read_u16(pos);
-                addr
-            }
-
-            // Indexed Indirect X addressing functions like a cross between
-            // Zero Page X and Indirect. The memory address pointed at by
-            // what's held at the Zero Page + Register X address is our final
-            // address.
-            AddressingMode::IndirectX => {
-                let pos = self.mem_read(self.program_counter);
-                let ptr = pos.wrapping_add(self.register_x) as u16;
-                let addr = self.mem_read_u16(ptr);
-                addr
-            }
-
-            // Same as Indexed Indirect X, but with Register Y.
-            AddressingMode::IndirectY => {
-                let pos = self.mem_read(self.program_counter);
-                let ptr = pos.wrapping_add(self.register_y) as u16;
-                let addr = self.mem_read_u16(ptr);
-                addr
-            }
-
-            // Operand is the accumulator itself.
-            AddressingMode::Accumulator => {
-                panic!("mode {:?} does not return a memory address", mode)
+    pub(crate) fn mem_write(&mut self, addr: u16, data: u8) {
+        #[cfg(any(test, feature = "testing"))]
+        self.trace_access(addr, data, AccessKind::Write);
+        if self.write_log_capacity > 0 {
+            self.log_write(addr, data);
+        }
+        if self.serial_port == Some(addr) {
+            self.serial_output.push(data as char);
+            return;
+        }
+        if self.undo_recording {
+            let old = self.memory[addr as usize];
+            if let Some(entry) = self.undo_ring.back_mut() {
+                entry.writes.push((addr, old));
             }
-
-            // If nothing matches, panic.
-            AddressingMode::NoneAddressing => panic!("mode {:?} is not supported", mode),
         }
+        self.memory[addr as usize] = data;
     }
 
     /**
-     * Run the program on the CPU.
+     * Install a tracer receiving every memory read and write.
+     *
+     * This sees all bus traffic, including instruction fetches and the
+     * pointer reads of indirect addressing, making it suitable for full
+     * access logs. Reads made by debugging tools through `peek()` are not
+     * reported. Only available with the `testing` feature, so that normal
+     * builds pay nothing for it.
+     *
+     * @param tracer Called with each access as it happens.
      */
-    pub fn run(&mut self, program: Vec<u8>) {
-        self.load(program);
-        self.reset();
-        self.execute();
+    #[cfg(any(test, feature = "testing"))]
+    pub fn set_access_tracer<F: FnMut(&MemoryAccess) + 'static>(&mut self, tracer: F) {
+        *self.access_tracer.borrow_mut() = Some(Box::new(tracer));
     }
 
     /**
-     * Load program into memory.
+     * Remove the tracer installed by `set_access_tracer()`.
      */
-    pub fn load(&mut self, program: Vec<u8>) {
-        let program_end = NES_ROM_PROGRAM_START + program.len();
-        self.memory[NES_ROM_PROGRAM_START..program_end].copy_from_slice(&program[..]);
-
-        self.mem_write_u16(0xFFFC, NES_ROM_PROGRAM_START as u16);
-        self.program_counter = NES_ROM_PROGRAM_START as u16;
+    #[cfg(any(test, feature = "testing"))]
+    pub fn clear_access_tracer(&mut self) {
+        *self.access_tracer.borrow_mut() = None;
     }
 
     /**
-     * Reset CPU registers and initialize program counter.
+     * Report a memory access to the installed tracer, if any.
      */
-    pub fn reset(&mut self) {
-        self.register_a = 0;
-        self.register_x = 0;
-        self.status = 0;
-        self.program_counter = self.mem_read_u16(0xFFFC);
+    #[cfg(any(test, feature = "testing"))]
+    fn trace_access(&self, addr: u16, value: u8, kind: AccessKind) {
+        if let Some(tracer) = self.access_tracer.borrow_mut().as_mut() {
+            tracer(&MemoryAccess {
+                pc: self.instruction_pc,
+                addr,
+                value,
+                kind,
+            });
+        }
     }
 
     /**
-     * Execute the program from system memory.
+     * Keep a log of the most recent memory writes.
      *
-     * Requires that a program has been `load()`ed and that the CPU has
-     * been `reset()` first.
+     * Handy for post-mortem debugging: after memory is found corrupted, the
+     * log shows which instructions wrote to it last. Changing the capacity
+     * clears the log.
+     *
+     * @param capacity The number of writes to keep, or 0 to disable logging.
      */
-    pub fn execute(&mut self) {
-        let ref opcodes: HashMap<u8, &'static opcodes::OpCode> = *opcodes::CPU_OPCODES_MAP;
-
-        loop {
-            let opcode = self.mem_read(self.program_counter);
-            self.program_counter += 1;
-
-            let info = opcodes
-                .get(&opcode)
-                .expect(&format!("Unrecognized opcode: {:x}", opcode));
-
-            match opcode {
-                0xE8 => self.inx(),
-
-                0xA9 | 0xA5 | 0xB5 | 0xAD | 0xBD | 0xB9 | 0xA1 | 0xB1 => {
-                    self.lda(&info.mode);
-                }
-
-                0xA2 | 0xA6 | 0xB6 | 0xAE | 0xBE => {
-                    self.ldx(&info.mode);
-                }
-
-                0xA0 | 0xA4 | 0xB4 | 0xAC | 0xBC => {
-                    self.ldy(&info.mode);
-                }
-
-                0x85 | 0x95 | 0x8D | 0x9D | 0x99 | 0x81 | 0x91 => {
-                    self.sta(&info.mode);
-                }
-
-                0xAA => self.tax(),
-
-                // BRK
-                0x00 => return,
-                _ => todo!(),
-            }
+    pub fn set_write_log(&mut self, capacity: usize) {
+        self.write_log_capacity = capacity;
+        self.write_log = Vec::new();
+    }
 
-            self.program_counter += (info.length - 1) as u16;
-        }
+    /**
+     * The most recent memory writes, oldest first.
+     */
+    pub fn recent_writes(&self) -> &[WriteEvent] {
+        let start = self.write_log.len().saturating_sub(self.write_log_capacity);
+        &self.write_log[start..]
     }
 
     /**
-     * 6502 Increment X Register
+     * Append a write to the write log.
      *
-     * Adds one to the X register setting the zero and negative flags as
-     * appropriate.
+     * The log is allowed to grow to twice its capacity before the older half
+     * is dropped, so that recording a write stays cheap while the most
+     * recent writes remain contiguous.
      */
-    fn inx(&mut self) {
-        // Check for overflow
-        if self.register_x == u8::max_value() {
-            self.register_x = 0;
-        } else {
-            self.register_x += 1;
+    fn log_write(&mut self, addr: u16, value: u8) {
+        if self.write_log.len() == self.write_log_capacity.saturating_mul(2) {
+            self.write_log.drain(..self.write_log_capacity);
         }
-        self.set_cpu_status_flags(self.register_x);
+        self.write_log.push(WriteEvent {
+            pc: self.instruction_pc,
+            addr,
+            value,
+        });
     }
 
     /**
-     * 6502 Load Accumulator
+     * Map a serial console output port into memory.
      *
-     * Load a byte of memory into the accumulator setting the zero and
-     * negative flags as appropriate.
+     * Bare 6502 test programs commonly print by storing characters to a
+     * fixed address. Bytes written to the port are collected as text
+     * instead of being stored in memory.
+     *
+     * @param addr The address of the port, or `None` to unmap it.
      */
-    fn lda(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
-        self.register_a = value;
-        self.set_cpu_status_flags(self.register_a);
+    pub fn set_serial_port(&mut self, addr: Option<u16>) {
+        self.serial_port = addr;
     }
 
     /**
-     * 6502 Load X Register
-     *
-     * Load a byte of memory into the X register setting the zero and
+     * Everything written to the serial console port so far.
+     */
+    pub fn serial_output(&self) -> &str {
+        &self.serial_output
+    }
+
+    /**
+     * Write a word to a location in memory.
+     *
+     * This function writes data to memory, packed in little-endian format.
+     *
+     * @param pos The position in memory to which to write.
+     * @param data The word to write to the address.
+     */
+    fn mem_write_u16(&mut self, pos: u16, data: u16) {
+        let bytes = data.to_le_bytes();
+        let lower = bytes[0];
+        let upper = bytes[1];
+        self.mem_write(pos, lower);
+        self.mem_write(pos.wrapping_add(1), upper);
+    }
+
+    /**
+     * Capture the registers and cycle count.
+     *
+     * Memory is not included, making this cheap enough to take before every
+     * instruction.
+     */
+    pub fn state(&self) -> CpuState {
+        CpuState {
+            a: self.register_a,
+            x: self.register_x,
+            y: self.register_y,
+            sp: self.stack_pointer,
+            pc: self.program_counter,
+            status: self.status,
+            cycles: self.cycles,
+        }
+    }
+
+    /**
+     * Put the registers and cycle count back as they were captured.
+     *
+     * Memory is left as it is.
+     *
+     * @param state A state previously returned by `state()`.
+     */
+    pub fn restore(&mut self, state: CpuState) {
+        self.register_a = state.a;
+        self.register_x = state.x;
+        self.register_y = state.y;
+        self.stack_pointer = state.sp;
+        self.program_counter = state.pc;
+        self.status = state.status;
+        self.cycles = state.cycles;
+    }
+
+    /**
+     * The processor status register as software observes it.
+     *
+     * The unused bit 5 has no storage on the 6502 and always reads as 1,
+     * both here and when the status is pushed to the stack.
+     */
+    pub fn p(&self) -> u8 {
+        self.status | STATUS_UNUSED
+    }
+
+    /**
+     * The accumulator.
+     */
+    pub fn a(&self) -> u8 {
+        self.register_a
+    }
+
+    /**
+     * Set the accumulator. Unlike a load instruction, this leaves the
+     * status flags untouched, as do the other register setters.
+     */
+    pub fn set_a(&mut self, value: u8) {
+        self.register_a = value;
+    }
+
+    /**
+     * The X register.
+     */
+    pub fn x(&self) -> u8 {
+        self.register_x
+    }
+
+    /**
+     * Set the X register, leaving the status flags untouched.
+     */
+    pub fn set_x(&mut self, value: u8) {
+        self.register_x = value;
+    }
+
+    /**
+     * The Y register.
+     */
+    pub fn y(&self) -> u8 {
+        self.register_y
+    }
+
+    /**
+     * Set the Y register, leaving the status flags untouched.
+     */
+    pub fn set_y(&mut self, value: u8) {
+        self.register_y = value;
+    }
+
+    /**
+     * The stack pointer, as an offset into page one.
+     */
+    pub fn sp(&self) -> u8 {
+        self.stack_pointer
+    }
+
+    /**
+     * Set the stack pointer.
+     */
+    pub fn set_sp(&mut self, value: u8) {
+        self.stack_pointer = value;
+    }
+
+    /**
+     * The program counter.
+     */
+    pub fn pc(&self) -> u16 {
+        self.program_counter
+    }
+
+    /**
+     * Set the program counter, so that execution continues from `addr`.
+     */
+    pub fn set_pc(&mut self, addr: u16) {
+        self.program_counter = addr;
+    }
+
+    /**
+     * Decode the processor status register.
+     */
+    pub fn flags(&self) -> Flags {
+        Flags {
+            carry: self.status & STATUS_CARRY != 0,
+            zero: self.status & STATUS_ZERO != 0,
+            interrupt_disable: self.status & STATUS_INTERRUPT_DISABLE != 0,
+            decimal: self.status & STATUS_DECIMAL_MODE != 0,
+            break_flag: self.status & STATUS_BREAK != 0,
+            overflow: self.status & STATUS_OVERFLOW != 0,
+            negative: self.status & STATUS_NEGATIVE != 0,
+        }
+    }
+
+    /**
+     * Whether a processor status flag is set.
+     *
+     * ```
+     * use cpu::cpu::{Flag, CPU};
+     *
+     * let mut cpu = CPU::new();
+     * cpu.run(vec![0xA9, 0x00, 0x00]); // LDA #$00; BRK
+     * assert!(cpu.is_flag_set(Flag::Zero));
+     * assert!(!cpu.is_flag_set(Flag::Negative));
+     * ```
+     *
+     * @param flag The flag to query.
+     */
+    pub fn is_flag_set(&self, flag: Flag) -> bool {
+        self.status & flag.mask() != 0
+    }
+
+    /**
+     * Record pushes and pulls that wrap the stack pointer.
+     *
+     * The stack pointer wraps silently on hardware, and still does with this
+     * enabled, but a wrap usually means the emulated program has a bug such
+     * as runaway recursion. Wraps are reported by `stack_wraps()`.
+     *
+     * @param enabled Whether to record stack wraps.
+     */
+    pub fn set_stack_debug(&mut self, enabled: bool) {
+        self.stack_debug = enabled;
+    }
+
+    /**
+     * The stack wraps recorded while `set_stack_debug()` was enabled, oldest
+     * first.
+     */
+    pub fn stack_wraps(&self) -> &[StackWrap] {
+        &self.stack_wraps
+    }
+
+    /**
+     * Push a byte onto the stack.
+     *
+     * @param data The byte to push.
+     */
+    fn stack_push(&mut self, data: u8) {
+        if self.stack_debug && self.stack_pointer == 0x00 {
+            let pc = self.instruction_pc;
+            self.stack_wraps.push(StackWrap::Overflow { pc });
+        }
+        self.mem_write(STACK + self.stack_pointer as u16, data);
+        self.stack_pointer = self.stack_pointer.wrapping_sub(1);
+    }
+
+    /**
+     * Pull a byte off of the stack.
+     *
+     * @return The byte at the top of the stack.
+     */
+    fn stack_pop(&mut self) -> u8 {
+        if self.stack_debug && self.stack_pointer == 0xFF {
+            let pc = self.instruction_pc;
+            self.stack_wraps.push(StackWrap::Underflow { pc });
+        }
+        self.stack_pointer = self.stack_pointer.wrapping_add(1);
+        self.mem_read(STACK + self.stack_pointer as u16)
+    }
+
+    /**
+     * Capture the current contents of memory.
+     */
+    #[cfg(any(test, feature = "testing"))]
+    pub fn snapshot_memory(&self) -> MemorySnapshot {
+        MemorySnapshot {
+            memory: self.memory.to_vec(),
+        }
+    }
+
+    /**
+     * List every memory cell that differs from a snapshot.
+     *
+     * @param snapshot The snapshot to compare against.
+     * @return `(addr, old, new)` for each changed cell, in address order.
+     */
+    #[cfg(any(test, feature = "testing"))]
+    pub fn memory_changed_since(&self, snapshot: &MemorySnapshot) -> Vec<(u16, u8, u8)> {
+        snapshot
+            .memory
+            .iter()
+            .zip(self.memory.iter())
+            .enumerate()
+            .filter(|(_, (old, new))| old != new)
+            .map(|(addr, (&old, &new))| (addr as u16, old, new))
+            .collect()
+    }
+
+    /**
+     * Determine the memory address of the argument pointed to by the PRG CTR.
+     *
+     * @param mode The type of addressing mode to use.
+     * @return The memory address from which we can locate a value.
+     */
+    fn get_operand_address(&self, mode: &AddressingMode) -> u16 {
+        self.resolve_address(mode, self.program_counter, CPU::mem_read)
+    }
+
+    /**
+     * Determine the memory address of an instruction's argument, without
+     * side effects.
+     *
+     * This is the same computation as `get_operand_address()`, but for an
+     * operand located anywhere in memory rather than at the PRG CTR, and
+     * using `peek()` to follow pointers. The tracer uses it to annotate
+     * instructions before they execute.
+     *
+     * @param mode The type of addressing mode to use.
+     * @param addr The position in memory of the instruction's operand.
+     * @return The memory address from which we can locate a value.
+     */
+    pub(crate) fn get_absolute_address(&self, mode: &AddressingMode, addr: u16) -> u16 {
+        self.resolve_address(mode, addr, CPU::peek)
+    }
+
+    /**
+     * Read the value of the argument pointed to by the PRG CTR.
+     *
+     * Instructions which only read their operand take an extra cycle when
+     * indexing carries into the next page, as the CPU must fix up the high
+     * byte of the address. Stores and read-modify-write instructions always
+     * spend that cycle, so it is already counted in their base cycles and
+     * they resolve their operand with `get_operand_address()` instead.
+     *
+     * @param mode The type of addressing mode to use.
+     * @return The value of the operand.
+     */
+    fn read_operand(&mut self, mode: &AddressingMode) -> u8 {
+        let addr = self.get_operand_address(mode);
+        let base = match mode {
+            AddressingMode::AbsoluteX | AddressingMode::AbsoluteY => {
+                Some(self.peek_u16(self.program_counter))
+            }
+            AddressingMode::IndirectY => {
+                let ptr = self.peek(self.program_counter);
+                Some(self.peek_u16_zp_wrap(ptr))
+            }
+            _ => None,
+        };
+        if let Some(base) = base {
+            if page_crossed(base, addr) {
+                self.extra_cycles += 1;
+            }
+        }
+        self.mem_read(addr)
+    }
+
+    /**
+     * Determine the memory address the instruction at the PRG CTR will
+     * access, without executing it.
+     *
+     * Pointers are followed with `peek()`, so this has no side effects.
+     * Debuggers can use it to show, for example, that a `STA $0200,X` is
+     * about to write to $0205.
+     *
+     * @param mode The addressing mode of the instruction.
+     * @return The memory address the instruction will access.
+     */
+    pub fn effective_address(&self, mode: &AddressingMode) -> u16 {
+        self.get_absolute_address(mode, self.program_counter.wrapping_add(1))
+    }
+
+    /**
+     * Look up the addressing mode of the instruction at the PRG CTR,
+     * without executing it.
+     *
+     * The result can be passed to `effective_address()`.
+     *
+     * @return The addressing mode, or `None` if the byte at the PRG CTR is
+     *         not a known opcode.
+     */
+    pub fn current_addressing_mode(&self) -> Option<&'static AddressingMode> {
+        opcodes::addressing_mode(self.peek(self.program_counter))
+    }
+
+    /**
+     * Common implementation of operand address resolution.
+     *
+     * @param mode The type of addressing mode to use.
+     * @param addr The position in memory of the instruction's operand.
+     * @param read How to read memory while following the operand.
+     * @return The memory address from which we can locate a value.
+     */
+    fn resolve_address(&self, mode: &AddressingMode, addr: u16, read: fn(&CPU, u16) -> u8) -> u16 {
+        let read_u16 =
+            |pos: u16| u16::from_le_bytes([read(self, pos), read(self, pos.wrapping_add(1))]);
+
+        match mode {
+            // Immediate addressing does not rely on a memory address and loads
+            // the value into the register immediately. When a program is
+            // running, the immediate value to load is that which is pointed at
+            // by the program counter in memory.
+            AddressingMode::Immediate => addr,
+
+            // Absolute addressing uses the full memory location to locate
+            // a value.
+            AddressingMode::Absolute => read_u16(addr),
+
+            // Like Absolute addressing, but the value of Register X is added
+            // to determine the final address.
+            AddressingMode::AbsoluteX => {
+                let pos = read_u16(addr);
+                pos.wrapping_add(self.register_x as u16)
+            }
+
+            // Like Absolute addressing, but the value of Register Y is added
+            // to determine the final address.
+            AddressingMode::AbsoluteY => {
+                let pos = read_u16(addr);
+                pos.wrapping_add(self.register_y as u16)
+            }
+
+            // Zero Page addressing only reads from the first page of memory.
+            // Think: Zero-indexing. This means the address we need to read
+            // is at 0x00nn. Functions the same as Absolute addressing.
+            AddressingMode::ZeroPage => read(self, addr) as u16,
+
+            // Like Zero Page addressing, but the value of Register X is added
+            // to determine the final address.
+            AddressingMode::ZeroPageX => {
+                let pos = read(self, addr);
+                pos.wrapping_add(self.register_x) as u16
+            }
+
+            // Like Zero Page addressing, but the value of Register Y is added
+            // to determine the final address.
+            AddressingMode::ZeroPageY => {
+                let pos = read(self, addr);
+                pos.wrapping_add(self.register_y) as u16
+            }
+
+            // With Indirect addressing, the memory address that the PRG CTR
+            // points to is itself pointing at another memory address. To
+            // determine the final address, we dereference twice. The 6502
+            // never carries into the high byte of the pointer, so a pointer
+            // at $xxFF reads its upper byte from $xx00.
+            AddressingMode::Indirect => {
+                let pos = read_u16(addr);
+                let lower = read(self, pos);
+                let upper = read(self, (pos & 0xFF00) | (pos.wrapping_add(1) & 0x00FF));
+                u16::from_le_bytes([lower, upper])
+            }
+
+            // Indexed Indirect X addressing functions like a cross between
+            // Zero Page X and Indirect. The memory address pointed at by
+            // what's held at the Zero Page + Register X address is our final
+            // address. The pointer wraps within the Zero Page.
+            AddressingMode::IndirectX => {
+                let pos = read(self, addr);
+                self.zp_u16(pos.wrapping_add(self.register_x), read)
+            }
+
+            // Indirect Indexed Y addressing dereferences the Zero Page
+            // address first, then adds Register Y to the pointer found there
+            // to determine the final address.
+            AddressingMode::IndirectY => {
+                let pos = read(self, addr);
+                self.zp_u16(pos, read).wrapping_add(self.register_y as u16)
+            }
+
+            // Relative addressing is used by branches. The operand is a signed
+            // offset from the address of the next instruction. Widening the
+            // i8 sign-extends it, so the wrapping add moves backward for
+            // offsets of $80-$FF.
+            AddressingMode::Relative => {
+                let offset = read(self, addr) as i8;
+                addr.wrapping_add(1).wrapping_add(offset as u16)
+            }
+
+            // Operand is the accumulator itself.
+            AddressingMode::Accumulator => {
+                panic!("mode {:?} does not return a memory address", mode)
+            }
+
+            // If nothing matches, panic.
+            AddressingMode::NoneAddressing => panic!("mode {:?} is not supported", mode),
+        }
+    }
+
+    /**
+     * Run the program on the CPU.
+     *
+     * @return `Halted`, `Jammed` if the program hit a JAM opcode, or
+     *         `UnknownOpcode` if it reached one the CPU cannot execute.
+     */
+    pub fn run(&mut self, program: Vec<u8>) -> RunResult {
+        self.load(program);
+        self.reset();
+        self.execute()
+    }
+
+    /**
+     * Run the program on the CPU, giving up after a number of instructions.
+     *
+     * A safety net for test suites, so a program that never reaches BRK
+     * cannot hang the run.
+     *
+     * @param program          The program to run.
+     * @param max_instructions The most instructions to execute.
+     * @return `BudgetExhausted` if the program neither halted nor jammed
+     *         within the limit.
+     */
+    pub fn run_with_limit(&mut self, program: Vec<u8>, max_instructions: u64) -> RunResult {
+        self.load(program);
+        self.reset();
+        self.execute_with_limit(max_instructions)
+    }
+
+    /**
+     * Run the program on the CPU, calling back after each instruction.
+     *
+     * The callback gets the CPU to inspect or drive, e.g. to read
+     * `last_instruction_cycles()` or to raise an interrupt. Execution stops
+     * once the CPU halts.
+     *
+     * @param program  The program to run.
+     * @param callback Called after each instruction executes.
+     * @return `Halted`, `Jammed` if the program hit a JAM opcode, or
+     *         `UnknownOpcode` if it reached one the CPU cannot execute.
+     */
+    pub fn run_with_callback<F: FnMut(&mut CPU)>(
+        &mut self,
+        program: Vec<u8>,
+        mut callback: F,
+    ) -> RunResult {
+        self.load(program);
+        self.reset();
+        self.resume();
+        while !self.halted {
+            if self.run_step() {
+                callback(self);
+            }
+        }
+        self.stop_reason()
+    }
+
+    /**
+     * Load program into memory.
+     *
+     * Panics if the program does not fit between $8000 and the end of
+     * memory; use `try_load()` to handle that case.
+     */
+    pub fn load(&mut self, program: Vec<u8>) {
+        self.try_load(program)
+            .unwrap_or_else(|err| panic!("{}", err));
+    }
+
+    /**
+     * Load program into memory, unless it is too large.
+     *
+     * @return An error, leaving memory unchanged, if the program does not
+     *         fit between $8000 and the end of memory.
+     */
+    pub fn try_load(&mut self, program: Vec<u8>) -> Result<(), LoadError> {
+        self.load_memory(NES_ROM_PROGRAM_START, &program)?;
+
+        self.set_reset_vector(NES_ROM_PROGRAM_START);
+        self.program_counter = NES_ROM_PROGRAM_START;
+        Ok(())
+    }
+
+    /**
+     * Copy a block of data into memory, e.g. to set up a data table for a
+     * test.
+     *
+     * @param addr The address at which to place the first byte.
+     * @param data The bytes to copy.
+     * @return An error, leaving memory unchanged, if the data would run
+     *         past the end of memory.
+     */
+    pub fn load_memory(&mut self, addr: u16, data: &[u8]) -> Result<(), LoadError> {
+        let start = addr as usize;
+        let end = start + data.len();
+        if end > NES_MAX_MEMORY {
+            return Err(LoadError::DoesNotFit {
+                addr,
+                len: data.len(),
+            });
+        }
+        self.memory[start..end].copy_from_slice(data);
+        Ok(())
+    }
+
+    /**
+     * Force every read of an address to return a fixed value, as a Game
+     * Genie code does.
+     *
+     * Writes to the address still reach memory, but are hidden while the
+     * patch is in place. Replaces any patch already at the address.
+     *
+     * @param addr  The address to patch.
+     * @param value The value reads return.
+     */
+    pub fn add_patch(&mut self, addr: u16, value: u8) {
+        self.patches.insert(addr, value);
+    }
+
+    /**
+     * Remove the patch at an address, if there is one, revealing the value
+     * in memory.
+     */
+    pub fn remove_patch(&mut self, addr: u16) {
+        self.patches.remove(&addr);
+    }
+
+    /**
+     * Reset CPU registers and initialize program counter.
+     *
+     * This gives programs a clean, predictable starting state, which suits
+     * unit tests. Use `soft_reset()` to reset the CPU as hardware does.
+     */
+    pub fn reset(&mut self) {
+        self.register_a = 0;
+        self.register_x = 0;
+        self.status = 0;
+        self.stack_pointer = STACK_RESET;
+        self.cycles = 0;
+        self.halted = false;
+        self.jammed = false;
+        self.unknown_opcode = None;
+        self.program_counter = self.read_vector(RESET_VECTOR);
+    }
+
+    /**
+     * Reset the CPU as the reset line of a real 6502 does.
+     *
+     * The registers keep their values; the stack pointer is decremented by
+     * three, as for an interrupt that does not write to the stack,
+     * interrupts are disabled and decimal mode is cleared. The program
+     * counter is then loaded from the reset vector. The sequence takes 7
+     * cycles.
+     */
+    pub fn soft_reset(&mut self) {
+        self.stack_pointer = self.stack_pointer.wrapping_sub(3);
+        self.status |= STATUS_INTERRUPT_DISABLE;
+        self.status &= !STATUS_DECIMAL_MODE;
+        self.cycles += 7;
+        self.halted = false;
+        self.jammed = false;
+        self.unknown_opcode = None;
+        self.program_counter = self.read_vector(RESET_VECTOR);
+    }
+
+    /**
+     * Signal a non-maskable interrupt.
+     *
+     * The program counter and status are pushed on to the stack, with the
+     * break flag clear, and execution continues at the NMI handler.
+     */
+    pub fn nmi(&mut self) {
+        self.interrupt(NMI_VECTOR);
+    }
+
+    /**
+     * Signal an interrupt request.
+     *
+     * Like `nmi()`, but through the IRQ vector, and ignored while the
+     * interrupt disable flag is set.
+     */
+    pub fn irq(&mut self) {
+        if self.status & STATUS_INTERRUPT_DISABLE == 0 {
+            self.interrupt(IRQ_VECTOR);
+        }
+    }
+
+    /**
+     * Common implementation of the hardware interrupts.
+     *
+     * @param vector The vector holding the address of the handler.
+     */
+    fn interrupt(&mut self, vector: u16) {
+        let [lower, upper] = self.program_counter.to_le_bytes();
+        self.stack_push(upper);
+        self.stack_push(lower);
+        self.stack_push(self.p() & !STATUS_BREAK);
+        self.status |= STATUS_INTERRUPT_DISABLE;
+        self.cycles += 7;
+        self.halted = false;
+        self.program_counter = self.read_vector(vector);
+    }
+
+    /**
+     * The address of the NMI handler.
+     */
+    pub fn nmi_vector(&self) -> u16 {
+        self.read_vector(NMI_VECTOR)
+    }
+
+    /**
+     * Point the NMI vector at a handler.
+     */
+    pub fn set_nmi_vector(&mut self, addr: u16) {
+        self.mem_write_u16(NMI_VECTOR, addr);
+    }
+
+    /**
+     * The address execution starts from on reset.
+     */
+    pub fn reset_vector(&self) -> u16 {
+        self.read_vector(RESET_VECTOR)
+    }
+
+    /**
+     * Point the reset vector at the start of a program.
+     */
+    pub fn set_reset_vector(&mut self, addr: u16) {
+        self.mem_write_u16(RESET_VECTOR, addr);
+    }
+
+    /**
+     * The address of the IRQ handler, which is shared with BRK.
+     */
+    pub fn irq_vector(&self) -> u16 {
+        self.read_vector(IRQ_VECTOR)
+    }
+
+    /**
+     * Point the IRQ vector at a handler.
+     */
+    pub fn set_irq_vector(&mut self, addr: u16) {
+        self.mem_write_u16(IRQ_VECTOR, addr);
+    }
+
+    /**
+     * Execute the program from system memory.
+     *
+     * Requires that a program has been `load()`ed and that the CPU has
+     * been `reset()` first. Runs until a BRK halts the CPU, a JAM
+     * opcode locks it up or an opcode cannot be executed.
+     *
+     * @return `Halted`, `Jammed` or `UnknownOpcode`.
+     */
+    pub fn execute(&mut self) -> RunResult {
+        self.resume();
+        while !self.halted {
+            self.run_step();
+        }
+        self.stop_reason()
+    }
+
+    /**
+     * Execute the program until the CPU halts or a number of instructions
+     * have executed.
+     *
+     * @param max_instructions The most instructions to execute.
+     * @return `BudgetExhausted` if the CPU neither halted nor jammed
+     *         within the limit.
+     */
+    pub fn execute_with_limit(&mut self, max_instructions: u64) -> RunResult {
+        self.resume();
+        let mut executed = 0;
+        while !self.halted && executed < max_instructions {
+            self.run_step();
+            executed += 1;
+        }
+        if self.halted {
+            self.stop_reason()
+        } else {
+            RunResult::BudgetExhausted
+        }
+    }
+
+    /**
+     * Execute the program until a condition holds.
+     *
+     * The predicate is checked before each instruction, so execution stops
+     * with the CPU poised at the first instruction for which it held.
+     * Execution also stops if the CPU halts first.
+     *
+     * @param pred The condition to stop at, e.g. `|cpu| cpu.register_a == 0x42`.
+     */
+    pub fn run_until<F: FnMut(&CPU) -> bool>(&mut self, mut pred: F) {
+        self.resume();
+        while !self.halted && !pred(self) {
+            self.run_step();
+        }
+    }
+
+    /**
+     * Stop `run_to_breakpoint()` on reaching an instruction.
+     *
+     * Replaces any breakpoint already at the address, resetting its hit
+     * count.
+     *
+     * @param addr The address of the instruction.
+     */
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.add_breakpoint_after(addr, 1);
+    }
+
+    /**
+     * Stop `run_to_breakpoint()` on reaching an instruction for the `hits`th
+     * time, and every time after that, e.g. on the third iteration of a
+     * loop.
+     *
+     * @param addr The address of the instruction.
+     * @param hits The number of times the instruction must be reached.
+     */
+    pub fn add_breakpoint_after(&mut self, addr: u16, hits: u32) {
+        self.breakpoints.insert(
+            addr,
+            Breakpoint {
+                hits: 0,
+                break_after: hits,
+            },
+        );
+    }
+
+    /**
+     * Remove the breakpoint at an address, if there is one.
+     */
+    pub fn remove_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.remove(&addr);
+    }
+
+    /**
+     * How many times the breakpoint at an address has been reached.
+     *
+     * @return The hit count, or `None` if there is no breakpoint there.
+     */
+    pub fn breakpoint_hits(&self, addr: u16) -> Option<u32> {
+        self.breakpoints
+            .get(&addr)
+            .map(|breakpoint| breakpoint.hits)
+    }
+
+    /**
+     * Execute the program until a breakpoint stops it or the CPU halts.
+     *
+     * Execution stops with the CPU poised at the breakpoint's instruction.
+     * The instruction at the program counter when this is called is not
+     * checked, so calling it again resumes past the breakpoint that stopped
+     * it without counting another hit.
+     *
+     * @return `BreakpointHit` with the address of the breakpoint, or
+     *         `Halted` or `Jammed` if the CPU stopped first.
+     */
+    pub fn run_to_breakpoint(&mut self) -> RunResult {
+        self.resume();
+        let mut resuming = true;
+        while !self.halted {
+            let pc = self.program_counter;
+            if !resuming {
+                if let Some(breakpoint) = self.breakpoints.get_mut(&pc) {
+                    breakpoint.hits += 1;
+                    if breakpoint.hits >= breakpoint.break_after {
+                        return RunResult::BreakpointHit(pc);
+                    }
+                }
+            }
+            resuming = false;
+            self.run_step();
+        }
+        self.stop_reason()
+    }
+
+    /**
+     * Execute the program one instruction at a time as an iterator.
+     *
+     * Like `execute()`, the stream picks up from the current program
+     * counter and runs until the CPU halts, e.g.
+     * `for step in cpu.instructions().take(10)`.
+     */
+    pub fn instructions(&mut self) -> InstructionStream<'_> {
+        self.resume();
+        InstructionStream { cpu: self }
+    }
+
+    /**
+     * Whether the last instruction executed halted the CPU.
+     *
+     * The CPU halts on BRK, and on a jump to itself when
+     * `set_halt_on_jump_to_self()` is enabled.
+     */
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    /**
+     * Whether the last instruction executed was a JAM opcode.
+     *
+     * A jammed CPU is also halted. It stays on the JAM opcode, so resuming
+     * execution jams again until the CPU is reset.
+     */
+    pub fn is_jammed(&self) -> bool {
+        self.jammed
+    }
+
+    /**
+     * Clear a previous halt before a run starts.
+     */
+    fn resume(&mut self) {
+        self.halted = false;
+        self.jammed = false;
+        self.unknown_opcode = None;
+    }
+
+    /**
+     * Execute one instruction of a run.
+     *
+     * Under `IllegalOpcodePolicy::Panic`, an opcode the CPU cannot execute
+     * halts the CPU with the PRG CTR still on it instead, so that the run
+     * can return `RunResult::UnknownOpcode`.
+     *
+     * @return Whether an instruction executed.
+     */
+    fn run_step(&mut self) -> bool {
+        if self.illegal_opcode_policy == IllegalOpcodePolicy::Panic {
+            let opcode = self.peek(self.program_counter);
+            if !opcodes::CPU_OPCODES_MAP.contains_key(&opcode) {
+                self.unknown_opcode = Some(opcode);
+                self.halted = true;
+                return false;
+            }
+        }
+        self.step();
+        true
+    }
+
+    /**
+     * Why a halted CPU stopped.
+     */
+    fn stop_reason(&self) -> RunResult {
+        if let Some(opcode) = self.unknown_opcode {
+            RunResult::UnknownOpcode(opcode)
+        } else if self.jammed {
+            RunResult::Jammed
+        } else {
+            RunResult::Halted
+        }
+    }
+
+    /**
+     * Treat a JMP or branch to its own address as the end of the program.
+     *
+     * Homebrew and test programs commonly finish with `JMP *`, spinning
+     * forever. With this enabled the CPU halts on such an instruction
+     * instead, so headless runs terminate. Disabled by default, as real
+     * programs also spin this way while waiting for an interrupt.
+     *
+     * @param enabled Whether to halt on a jump to self.
+     */
+    pub fn set_halt_on_jump_to_self(&mut self, enabled: bool) {
+        self.halt_on_jump_to_self = enabled;
+    }
+
+    /**
+     * Choose what happens on reaching an opcode the CPU cannot execute.
+     *
+     * @param policy The policy to apply from the next instruction.
+     */
+    pub fn set_illegal_opcode_policy(&mut self, policy: IllegalOpcodePolicy) {
+        self.illegal_opcode_policy = policy;
+    }
+
+    /**
+     * The opcodes skipped under `IllegalOpcodePolicy::Nop`, as
+     * `(address, opcode)` pairs in the order they were reached.
+     */
+    pub fn skipped_opcodes(&self) -> &[(u16, u8)] {
+        &self.skipped_opcodes
+    }
+
+    /**
+     * Choose how the unstable opcodes ANE and LXA behave.
+     *
+     * Defaults to `UnstableOpcodes::Magic(0xEE)`, a value commonly seen on
+     * real hardware.
+     *
+     * @param behavior The behavior to use from the next instruction.
+     */
+    #[cfg(feature = "illegal_opcodes")]
+    pub fn set_unstable_opcodes(&mut self, behavior: UnstableOpcodes) {
+        match behavior {
+            UnstableOpcodes::Magic(magic) => self.unstable_magic = Some(magic),
+            UnstableOpcodes::Seeded(seed) => {
+                self.unstable_magic = None;
+                self.unstable_rng = rng_state(seed);
+            }
+        }
+    }
+
+    /**
+     * The magic constant for the next unstable opcode.
+     */
+    #[cfg(feature = "illegal_opcodes")]
+    fn unstable_magic(&mut self) -> u8 {
+        match self.unstable_magic {
+            Some(magic) => magic,
+            None => rng_next(&mut self.unstable_rng),
+        }
+    }
+
+    /**
+     * Override the base cycle count of an opcode.
+     *
+     * Lets timing experiments change the cycle model without editing the
+     * opcode table. Extra cycles, such as for a taken branch, are still
+     * added on top of the override, saturating at 255.
+     *
+     * @param opcode The opcode to override.
+     * @param cycles The base cycle count to use instead of the table's.
+     */
+    pub fn set_cycle_override(&mut self, opcode: u8, cycles: u8) {
+        self.cycle_overrides.insert(opcode, cycles);
+    }
+
+    /**
+     * Go back to the opcode table's cycle counts for every opcode.
+     */
+    pub fn clear_cycle_overrides(&mut self) {
+        self.cycle_overrides.clear();
+    }
+
+    /**
+     * Zero the cycle counter, e.g. to measure a region of a program.
+     *
+     * Nothing else about the CPU is changed.
+     */
+    pub fn reset_cycles(&mut self) {
+        self.cycles = 0;
+    }
+
+    /**
+     * Execute the single instruction pointed at by the program counter.
+     *
+     * Instructions are fetched from memory afresh every time, with no
+     * decoding cache, so code can execute from anywhere in memory, including
+     * RAM, and self-modifying code behaves as on hardware.
+     *
+     * @return The number of cycles the instruction took.
+     */
+    pub fn step(&mut self) -> u8 {
+        let cycles = if self.undo_depth == 0 {
+            self.execute_instruction()
+        } else {
+            if self.undo_ring.len() == self.undo_depth {
+                self.undo_ring.pop_front();
+            }
+            self.undo_ring.push_back(UndoEntry {
+                state: self.state(),
+                halted: self.halted,
+                jammed: self.jammed,
+                writes: Vec::new(),
+            });
+            self.undo_recording = true;
+            let cycles = self.execute_instruction();
+            self.undo_recording = false;
+            cycles
+        };
+        self.last_instruction_cycles = cycles;
+        cycles
+    }
+
+    /**
+     * The number of cycles taken by the most recently executed instruction,
+     * as returned by `step()`.
+     */
+    pub fn last_instruction_cycles(&self) -> u8 {
+        self.last_instruction_cycles
+    }
+
+    /**
+     * Execute the single instruction at an address, to test an instruction
+     * in isolation.
+     *
+     * Unlike `run()`, nothing is loaded and the registers are not reset, so
+     * only the instruction itself needs writing to memory.
+     *
+     * @param pc The address of the instruction.
+     * @return The number of cycles the instruction took, and the state of
+     *         the CPU after it.
+     */
+    #[cfg(any(test, feature = "testing"))]
+    pub fn step_instruction_at(&mut self, pc: u16) -> (u8, CpuState) {
+        self.program_counter = pc;
+        let cycles = self.step();
+        (cycles, self.state())
+    }
+
+    /**
+     * Keep what is needed to step back over recently executed instructions.
+     *
+     * Before each instruction the registers are saved, along with the
+     * previous contents of any memory the instruction writes, which is far
+     * cheaper than snapshotting all of memory. Changing the depth forgets
+     * the instructions recorded so far.
+     *
+     * @param depth The number of instructions that can be stepped back
+     *              over, or 0 to disable recording.
+     */
+    pub fn set_undo_depth(&mut self, depth: usize) {
+        self.undo_depth = depth;
+        self.undo_ring = VecDeque::with_capacity(depth);
+    }
+
+    /**
+     * Undo the most recently executed instruction.
+     *
+     * Requires `set_undo_depth()` to have been enabled before the
+     * instruction executed.
+     *
+     * Only the registers, the halted and jammed state and memory are
+     * restored. `last_instruction_cycles()` still reports the undone
+     * instruction, and anything it added to `stack_wraps()`,
+     * `serial_output()` or `recent_writes()` is kept.
+     *
+     * @return Whether there was an instruction to undo.
+     */
+    pub fn step_back(&mut self) -> bool {
+        let entry = match self.undo_ring.pop_back() {
+            Some(entry) => entry,
+            None => return false,
+        };
+        for &(addr, old) in entry.writes.iter().rev() {
+            self.memory[addr as usize] = old;
+        }
+        self.restore(entry.state);
+        self.halted = entry.halted;
+        self.jammed = entry.jammed;
+        true
+    }
+
+    /**
+     * Decode and execute the instruction pointed at by the program counter.
+     *
+     * @return The number of cycles the instruction took.
+     */
+    fn execute_instruction(&mut self) -> u8 {
+        let opcodes: &HashMap<u8, &'static opcodes::OpCode> = &opcodes::CPU_OPCODES_MAP;
+
+        self.instruction_pc = self.program_counter;
+        let opcode = self.mem_read(self.program_counter);
+        self.program_counter = self.program_counter.wrapping_add(1);
+
+        let info = match opcodes.get(&opcode) {
+            Some(info) => info,
+            None => {
+                if self.illegal_opcode_policy == IllegalOpcodePolicy::Panic {
+                    panic!("Unrecognized opcode: {:x}", opcode);
+                }
+                // With no length to go by, treat it as a one byte NOP.
+                self.skipped_opcodes.push((self.instruction_pc, opcode));
+                self.cycles += 2;
+                return 2;
+            }
+        };
+
+        let jumped = match self.dispatch(opcode, &info.mode) {
+            Some(jumped) => jumped,
+            None => {
+                if self.illegal_opcode_policy == IllegalOpcodePolicy::Panic {
+                    panic!("Unrecognized opcode: {:x}", opcode);
+                }
+                self.skipped_opcodes.push((self.instruction_pc, opcode));
+                false
+            }
+        };
+
+        // Jumps and taken branches have already moved the PRG CTR.
+        if !jumped {
+            self.program_counter = self.program_counter.wrapping_add((info.length - 1) as u16);
+        }
+
+        let base = match self.cycle_overrides.get(&opcode) {
+            Some(&cycles) => cycles,
+            None => info.cycles,
+        };
+        let cycles = base.saturating_add(self.extra_cycles);
+        self.extra_cycles = 0;
+        self.cycles += cycles as u64;
+        cycles
+    }
+
+    /**
+     * Carry out the operation of an opcode, with the PRG CTR just past the
+     * opcode byte. This is the one place that says which opcodes are
+     * implemented.
+     *
+     * @param opcode The opcode to execute.
+     * @param mode The addressing mode of the opcode.
+     * @return Whether the instruction moved the PRG CTR itself, or `None`
+     *         if the opcode is not implemented.
+     */
+    fn dispatch(&mut self, opcode: u8, mode: &AddressingMode) -> Option<bool> {
+        let mut jumped = false;
+        match opcode {
+            0xE8 => self.inx(),
+
+            0xE6 | 0xF6 | 0xEE | 0xFE => self.inc(mode),
+            0xC6 | 0xD6 | 0xCE | 0xDE => self.dec(mode),
+
+            #[cfg(feature = "illegal_opcodes")]
+            0xA7 | 0xB7 | 0xAF | 0xBF | 0xA3 | 0xB3 => {
+                self.lax(mode);
+            }
+
+            #[cfg(feature = "illegal_opcodes")]
+            0x8B => self.ane(mode),
+            #[cfg(feature = "illegal_opcodes")]
+            0xAB => self.lxa(mode),
+
+            0xA9 | 0xA5 | 0xB5 | 0xAD | 0xBD | 0xB9 | 0xA1 | 0xB1 => {
+                self.lda(mode);
+            }
+
+            0xA2 | 0xA6 | 0xB6 | 0xAE | 0xBE => {
+                self.ldx(mode);
+            }
+
+            0xA0 | 0xA4 | 0xB4 | 0xAC | 0xBC => {
+                self.ldy(mode);
+            }
+
+            0x85 | 0x95 | 0x8D | 0x9D | 0x99 | 0x81 | 0x91 => {
+                self.sta(mode);
+            }
+
+            0xAA => self.tax(),
+
+            0x29 | 0x25 | 0x35 | 0x2D | 0x3D | 0x39 | 0x21 | 0x31 => {
+                self.and(mode);
+            }
+
+            0x0A | 0x06 | 0x16 | 0x0E | 0x1E => {
+                self.asl(mode);
+            }
+
+            0x69 | 0x65 | 0x75 | 0x6D | 0x7D | 0x79 | 0x61 | 0x71 => {
+                self.adc(mode);
+            }
+
+            0xE9 | 0xE5 | 0xF5 | 0xED | 0xFD | 0xF9 | 0xE1 | 0xF1 => {
+                self.sbc(mode);
+            }
+
+            0x18 => self.status &= !STATUS_CARRY,
+            0x38 => self.status |= STATUS_CARRY,
+            0xD8 => self.status &= !STATUS_DECIMAL_MODE,
+            0xF8 => self.status |= STATUS_DECIMAL_MODE,
+
+            0xC9 | 0xC5 | 0xD5 | 0xCD | 0xDD | 0xD9 | 0xC1 | 0xD1 => {
+                self.compare(mode, self.register_a);
+            }
+
+            0xE0 | 0xE4 | 0xEC => {
+                self.compare(mode, self.register_x);
+            }
+
+            0xC0 | 0xC4 | 0xCC => {
+                self.compare(mode, self.register_y);
+            }
+
+            0x08 => self.php(),
+            0x28 => self.plp(),
+
+            0x4C | 0x6C => {
+                self.jmp(mode);
+                jumped = true;
+            }
+
+            0x10 => jumped = self.branch(self.status & STATUS_NEGATIVE == 0),
+            0x30 => jumped = self.branch(self.status & STATUS_NEGATIVE != 0),
+            0x50 => jumped = self.branch(self.status & STATUS_OVERFLOW == 0),
+            0x70 => jumped = self.branch(self.status & STATUS_OVERFLOW != 0),
+            0x90 => jumped = self.branch(self.status & STATUS_CARRY == 0),
+            0xB0 => jumped = self.branch(self.status & STATUS_CARRY != 0),
+            0xD0 => jumped = self.branch(self.status & STATUS_ZERO == 0),
+            0xF0 => jumped = self.branch(self.status & STATUS_ZERO != 0),
+
+            0x00 => self.brk(),
+
+            #[cfg(feature = "illegal_opcodes")]
+            0x02 | 0x12 | 0x22 | 0x32 | 0x42 | 0x52 | 0x62 | 0x72 | 0x92 | 0xB2 | 0xD2 | 0xF2 => {
+                self.jam();
+                jumped = true;
+            }
+
+            _ => return None,
+        }
+        Some(jumped)
+    }
+
+    /**
+     * 6502 Increment X Register
+     *
+     * Adds one to the X register setting the zero and negative flags as
+     * appropriate.
+     */
+    fn inx(&mut self) {
+        self.register_x = self.register_x.wrapping_add(1);
+        self.set_cpu_status_flags(self.register_x);
+    }
+
+    /**
+     * 6502 Logical AND
+     *
+     * A logical AND is performed, bit by bit, on the accumulator contents
+     * using the contents of a byte of memory.
+     */
+    fn and(&mut self, mode: &AddressingMode) {
+        let value = self.read_operand(mode);
+        self.register_a &= value;
+        self.set_cpu_status_flags(self.register_a);
+    }
+
+    /**
+     * 6502 Arithmetic Shift Left
+     *
+     * Shifts the bits of the accumulator or a byte of memory one place to
+     * the left. Bit 7 is placed in the carry flag and bit 0 is cleared.
+     */
+    fn asl(&mut self, mode: &AddressingMode) {
+        let (addr, value) = match mode {
+            AddressingMode::Accumulator => (None, self.register_a),
+            _ => {
+                let addr = self.get_operand_address(mode);
+                (Some(addr), self.mem_read(addr))
+            }
+        };
+
+        if value & 0x80 != 0 {
+            self.status |= STATUS_CARRY;
+        } else {
+            self.status &= !STATUS_CARRY;
+        }
+        let result = value << 1;
+
+        match addr {
+            Some(addr) => self.mem_write(addr, result),
+            None => self.register_a = result,
+        }
+        self.set_cpu_status_flags(result);
+    }
+
+    /**
+     * 6502 Increment Memory
+     *
+     * Adds one to the value held at a specified memory location setting
+     * the zero and negative flags as appropriate. As a read-modify-write
+     * instruction, its base cycles already include the indexing fix-up.
+     */
+    fn inc(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        let result = self.mem_read(addr).wrapping_add(1);
+        self.mem_write(addr, result);
+        self.set_cpu_status_flags(result);
+    }
+
+    /**
+     * 6502 Decrement Memory
+     *
+     * Subtracts one from the value held at a specified memory location
+     * setting the zero and negative flags as appropriate.
+     */
+    fn dec(&mut self, mode: &AddressingMode) {
+        let addr = self.get_operand_address(mode);
+        let result = self.mem_read(addr).wrapping_sub(1);
+        self.mem_write(addr, result);
+        self.set_cpu_status_flags(result);
+    }
+
+    /**
+     * 6502 Add with Carry
+     *
+     * Adds the contents of a memory location to the accumulator together
+     * with the carry bit. If overflow occurs the carry bit is set. The 2A03
+     * has no decimal mode, so unless built for a `generic_6502` the decimal
+     * flag is ignored.
+     */
+    fn adc(&mut self, mode: &AddressingMode) {
+        let value = self.read_operand(mode);
+
+        #[cfg(feature = "generic_6502")]
+        if self.status & STATUS_DECIMAL_MODE != 0 {
+            #[cfg(feature = "cmos_65c02")]
+            self.decimal_cycle_penalty();
+            self.add_decimal(value);
+            return;
+        }
+
+        self.add_to_register_a(value);
+    }
+
+    /**
+     * 6502 Subtract with Carry
+     *
+     * Subtracts the contents of a memory location from the accumulator
+     * together with the not of the carry bit. If overflow occurs the carry
+     * bit is clear. As with ADC, decimal mode only exists on a
+     * `generic_6502`.
+     */
+    fn sbc(&mut self, mode: &AddressingMode) {
+        let value = self.read_operand(mode);
+
+        #[cfg(feature = "generic_6502")]
+        if self.status & STATUS_DECIMAL_MODE != 0 {
+            #[cfg(feature = "cmos_65c02")]
+            self.decimal_cycle_penalty();
+            self.subtract_decimal(value);
+            return;
+        }
+
+        // A - M - (1 - C) is the same as A + !M + C in two's complement.
+        self.add_to_register_a(!value);
+    }
+
+    /**
+     * The 65C02 spends an extra cycle on decimal mode ADC and SBC. Only the
+     * timing is modelled; the flags still follow the NMOS 6502.
+     */
+    #[cfg(feature = "cmos_65c02")]
+    fn decimal_cycle_penalty(&mut self) {
+        self.extra_cycles += 1;
+    }
+
+    /**
+     * Binary addition of a value and the carry bit into the accumulator.
+     *
+     * The overflow flag is set when both inputs have the same sign and the
+     * result's sign differs from it.
+     */
+    fn add_to_register_a(&mut self, value: u8) {
+        let sum = self.register_a as u16 + value as u16 + (self.status & STATUS_CARRY) as u16;
+        let result = sum as u8;
+
+        if sum > 0xFF {
+            self.status |= STATUS_CARRY;
+        } else {
+            self.status &= !STATUS_CARRY;
+        }
+
+        if (self.register_a ^ result) & (value ^ result) & 0x80 != 0 {
+            self.status |= STATUS_OVERFLOW;
+        } else {
+            self.status &= !STATUS_OVERFLOW;
+        }
+
+        self.register_a = result;
+        self.set_cpu_status_flags(self.register_a);
+    }
+
+    /**
+     * Binary-coded decimal addition of a value and the carry bit into the
+     * accumulator.
+     *
+     * Only the carry flag reflects the decimal result. As on the NMOS 6502,
+     * the zero flag comes from the binary sum, while the negative and
+     * overflow flags come from the sum after adjusting the lower digit but
+     * before adjusting the upper one.
+     */
+    #[cfg(feature = "generic_6502")]
+    fn add_decimal(&mut self, value: u8) {
+        let a = self.register_a;
+        let carry = (self.status & STATUS_CARRY) as u16;
+
+        let mut lower = (a & 0x0F) as u16 + (value & 0x0F) as u16 + carry;
+        if lower > 0x09 {
+            lower = ((lower + 0x06) & 0x0F) + 0x10;
+        }
+        let mut sum = (a & 0xF0) as u16 + (value & 0xF0) as u16 + lower;
+
+        let intermediate = sum as u8;
+        if !(a ^ value) & (a ^ intermediate) & 0x80 != 0 {
+            self.status |= STATUS_OVERFLOW;
+        } else {
+            self.status &= !STATUS_OVERFLOW;
+        }
+        if intermediate & 0x80 != 0 {
+            self.status |= STATUS_NEGATIVE;
+        } else {
+            self.status &= !STATUS_NEGATIVE;
+        }
+
+        let binary = a as u16 + value as u16 + carry;
+        if binary as u8 == 0 {
+            self.status |= STATUS_ZERO;
+        } else {
+            self.status &= !STATUS_ZERO;
+        }
+
+        if sum > 0x9F {
+            sum += 0x60;
+        }
+        if sum > 0xFF {
+            self.status |= STATUS_CARRY;
+        } else {
+            self.status &= !STATUS_CARRY;
+        }
+
+        self.register_a = sum as u8;
+    }
+
+    /**
+     * Binary-coded decimal subtraction of a value and the borrow from the
+     * accumulator.
+     *
+     * As on the NMOS 6502, all of the flags are those of the equivalent
+     * binary subtraction; only the accumulator holds the decimal result.
+     */
+    #[cfg(feature = "generic_6502")]
+    fn subtract_decimal(&mut self, value: u8) {
+        let a = self.register_a;
+        let borrow = 1 - (self.status & STATUS_CARRY) as i16;
+
+        let mut lower = (a & 0x0F) as i16 - (value & 0x0F) as i16 - borrow;
+        let mut upper = (a >> 4) as i16 - (value >> 4) as i16;
+        if lower < 0 {
+            lower -= 0x06;
+            upper -= 1;
+        }
+        if upper < 0 {
+            upper -= 0x06;
+        }
+        let result = ((upper << 4) | (lower & 0x0F)) as u8;
+
+        self.add_to_register_a(!value);
+        self.register_a = result;
+    }
+
+    /**
+     * Common implementation of the 6502 compare instructions.
+     *
+     * CMP, CPX and CPY subtract a byte of memory from a register without
+     * storing the result. The carry flag is set if the register is greater
+     * than or equal to the memory value; the zero and negative flags come
+     * from the difference, not from either operand.
+     *
+     * @param register The value of the register being compared.
+     */
+    fn compare(&mut self, mode: &AddressingMode, register: u8) {
+        let value = self.read_operand(mode);
+
+        if register >= value {
+            self.status |= STATUS_CARRY;
+        } else {
+            self.status &= !STATUS_CARRY;
+        }
+        self.set_cpu_status_flags(register.wrapping_sub(value));
+    }
+
+    /**
+     * 6502 Jump
+     *
+     * Sets the program counter to the address specified by the operand.
+     */
+    fn jmp(&mut self, mode: &AddressingMode) {
+        let target = self.get_operand_address(mode);
+        self.jump_to(target);
+    }
+
+    /**
+     * Common implementation of the 6502 branch instructions.
+     *
+     * Taking a branch costs an extra cycle, plus another if the target lies
+     * on a different page than the next instruction.
+     *
+     * @param condition Whether the branch should be taken.
+     * @return Whether the branch was taken.
+     */
+    fn branch(&mut self, condition: bool) -> bool {
+        if !condition {
+            return false;
+        }
+
+        let target = self.get_operand_address(&AddressingMode::Relative);
+        let next = self.program_counter.wrapping_add(1);
+        self.extra_cycles += 1;
+        if page_crossed(next, target) {
+            self.extra_cycles += 1;
+        }
+        self.jump_to(target);
+        true
+    }
+
+    /**
+     * Move the program counter to a jump or branch target.
+     */
+    fn jump_to(&mut self, target: u16) {
+        if self.halt_on_jump_to_self && target == self.instruction_pc {
+            self.halted = true;
+        }
+        self.program_counter = target;
+    }
+
+    /**
+     * Unofficial 6502 Load Accumulator and X Register
+     *
+     * Load a byte of memory into both the accumulator and the X register
+     * setting the zero and negative flags as appropriate.
+     */
+    #[cfg(feature = "illegal_opcodes")]
+    fn lax(&mut self, mode: &AddressingMode) {
+        let value = self.read_operand(mode);
+        self.register_a = value;
+        self.register_x = value;
+        self.set_cpu_status_flags(value);
+    }
+
+    /**
+     * Unstable unofficial 6502 AND X with Accumulator and Immediate
+     *
+     * Stores the accumulator, ORed with a magic constant, ANDed with the X
+     * register and an immediate value into the accumulator.
+     */
+    #[cfg(feature = "illegal_opcodes")]
+    fn ane(&mut self, mode: &AddressingMode) {
+        let value = self.read_operand(mode);
+        let magic = self.unstable_magic();
+        self.register_a = (self.register_a | magic) & self.register_x & value;
+        self.set_cpu_status_flags(self.register_a);
+    }
+
+    /**
+     * Unstable unofficial 6502 Load Accumulator and X Immediate
+     *
+     * Stores the accumulator, ORed with a magic constant and ANDed with an
+     * immediate value, into both the accumulator and the X register.
+     */
+    #[cfg(feature = "illegal_opcodes")]
+    fn lxa(&mut self, mode: &AddressingMode) {
+        let value = self.read_operand(mode);
+        let magic = self.unstable_magic();
+        self.register_a = (self.register_a | magic) & value;
+        self.register_x = self.register_a;
+        self.set_cpu_status_flags(self.register_a);
+    }
+
+    /**
+     * 6502 Load Accumulator
+     *
+     * Load a byte of memory into the accumulator setting the zero and
+     * negative flags as appropriate.
+     */
+    fn lda(&mut self, mode: &AddressingMode) {
+        let value = self.read_operand(mode);
+        self.register_a = value;
+        self.set_cpu_status_flags(self.register_a);
+    }
+
+    /**
+     * 6502 Load X Register
+     *
+     * Load a byte of memory into the X register setting the zero and
      * negative flags as appropriate.
      */
     fn ldx(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+        let value = self.read_operand(mode);
         self.register_x = value;
         self.set_cpu_status_flags(self.register_x);
     }
@@ -327,12 +2261,63 @@ impl CPU {
      * negative flags as appropriate.
      */
     fn ldy(&mut self, mode: &AddressingMode) {
-        let addr = self.get_operand_address(mode);
-        let value = self.mem_read(addr);
+        let value = self.read_operand(mode);
         self.register_y = value;
         self.set_cpu_status_flags(self.register_y);
     }
 
+    /**
+     * 6502 Force Interrupt
+     *
+     * Pushes the return address and the status, with the break flag set, on
+     * to the stack and disables interrupts. BRK is followed by a padding
+     * byte, so the return address is two bytes past the opcode. Rather than
+     * entering an interrupt handler, the CPU then halts, as BRK marks the
+     * end of a program.
+     */
+    fn brk(&mut self) {
+        let [lower, upper] = self.program_counter.wrapping_add(1).to_le_bytes();
+        self.stack_push(upper);
+        self.stack_push(lower);
+        self.stack_push(self.p() | STATUS_BREAK);
+        self.status |= STATUS_INTERRUPT_DISABLE;
+        self.halted = true;
+    }
+
+    /**
+     * 6502 Jam (unofficial)
+     *
+     * Locks up the CPU until it is reset. The program counter is left on
+     * the JAM opcode.
+     */
+    #[cfg(feature = "illegal_opcodes")]
+    fn jam(&mut self) {
+        self.program_counter = self.instruction_pc;
+        self.jammed = true;
+        self.halted = true;
+    }
+
+    /**
+     * 6502 Push Processor Status
+     *
+     * Pushes a copy of the status flags on to the stack. The break flag and
+     * the unused bit are always set in the pushed copy.
+     */
+    fn php(&mut self) {
+        self.stack_push(self.p() | STATUS_BREAK);
+    }
+
+    /**
+     * 6502 Pull Processor Status
+     *
+     * Pulls an 8 bit value from the stack and into the processor flags. The
+     * break flag only exists in pushed copies of the status, so it is
+     * discarded.
+     */
+    fn plp(&mut self) {
+        self.status = self.stack_pop() & !STATUS_BREAK;
+    }
+
     /**
      * 6502 Store Accumulator
      *
@@ -359,15 +2344,15 @@ impl CPU {
      */
     fn set_cpu_status_flags(&mut self, result: u8) {
         if result == 0 {
-            self.status = self.status | STATUS_ZERO;
+            self.status |= STATUS_ZERO;
         } else {
-            self.status = self.status & !STATUS_ZERO;
+            self.status &= !STATUS_ZERO;
         }
 
         if result & 0b1000_0000 != 0 {
-            self.status = self.status | STATUS_NEGATIVE;
+            self.status |= STATUS_NEGATIVE;
         } else {
-            self.status = self.status & !STATUS_NEGATIVE;
+            self.status &= !STATUS_NEGATIVE;
         }
     }
 }
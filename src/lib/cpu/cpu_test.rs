@@ -2,6 +2,8 @@
  * Unit tests for the CPU implementation.
  */
 use super::*;
+use std::cell::RefCell;
+use std::rc::Rc;
 
 #[test]
 fn test_0xa9_lda_immediate_load_data() {
@@ -51,6 +53,17 @@ fn test_0xa0_ldx_zero_flag() {
     assert!(cpu.status & STATUS_ZERO == 0b10);
 }
 
+#[test]
+#[cfg(feature = "illegal_opcodes")]
+fn test_0xa7_lax_loads_a_and_x() {
+    let mut cpu = CPU::new();
+    cpu.mem_write(0x10, 0x80);
+    cpu.run(vec![0xA7, 0x10, 0x00]);
+    assert_eq!(cpu.register_a, 0x80);
+    assert_eq!(cpu.register_x, 0x80);
+    assert_eq!(cpu.status & STATUS_NEGATIVE, STATUS_NEGATIVE);
+}
+
 #[test]
 fn test_0xaa_tax_move_a_to_x() {
     let mut cpu = CPU::new();
@@ -92,3 +105,1405 @@ fn test_lda_from_memory() {
     cpu.run(vec![0xa5, 0x10, 0x00]);
     assert_eq!(cpu.register_a, 0x55);
 }
+
+#[test]
+fn test_seed_ram_is_deterministic() {
+    let mut first = CPU::new();
+    let mut second = CPU::new();
+    first.seed_ram(0xC0FFEE);
+    second.seed_ram(0xC0FFEE);
+    assert_eq!(first.memory[..NES_RAM_SIZE], second.memory[..NES_RAM_SIZE]);
+    assert!(first.memory[..NES_RAM_SIZE].iter().any(|&b| b != 0));
+}
+
+#[test]
+fn test_program_reads_seeded_ram() {
+    let mut cpu = CPU::new();
+    cpu.seed_ram(42);
+    let seeded = cpu.mem_read(0x10);
+    cpu.run(vec![0xa5, 0x10, 0x00]);
+    assert_eq!(cpu.register_a, seeded);
+}
+
+#[test]
+fn test_0x08_php_pushes_unused_bit_set() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0x08, 0x00]);
+    cpu.reset();
+    cpu.status = 0;
+    cpu.step();
+    assert_eq!(cpu.stack_pointer, STACK_RESET - 1);
+    let pushed = cpu.mem_read(STACK + STACK_RESET as u16);
+    assert_eq!(pushed & STATUS_UNUSED, STATUS_UNUSED);
+    assert_eq!(pushed & STATUS_BREAK, STATUS_BREAK);
+}
+
+#[test]
+fn test_0x28_plp_restores_status() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0x08, 0x28, 0x00]);
+    cpu.reset();
+    cpu.status = STATUS_CARRY | STATUS_NEGATIVE;
+    cpu.step();
+    cpu.step();
+    assert_eq!(cpu.stack_pointer, STACK_RESET);
+    assert_eq!(cpu.status, STATUS_CARRY | STATUS_NEGATIVE | STATUS_UNUSED);
+    assert_eq!(cpu.p() & STATUS_UNUSED, STATUS_UNUSED);
+}
+
+#[test]
+fn test_p_reads_unused_bit_set() {
+    let mut cpu = CPU::new();
+    cpu.status = 0;
+    assert_eq!(cpu.p(), STATUS_UNUSED);
+}
+
+#[test]
+fn test_0x4c_jmp_absolute() {
+    let mut cpu = CPU::new();
+    cpu.run(vec![0x4C, 0x04, 0x80, 0xE8, 0x00]);
+    assert_eq!(cpu.register_x, 0);
+    assert_eq!(cpu.program_counter, 0x8006);
+}
+
+#[test]
+fn test_0x6c_jmp_indirect_page_boundary_bug() {
+    let mut cpu = CPU::new();
+    cpu.mem_write(0x02FF, 0x05);
+    cpu.mem_write(0x0200, 0x80);
+    cpu.mem_write(0x0300, 0x90);
+    cpu.run(vec![0x6C, 0xFF, 0x02, 0x00, 0x00, 0xE8, 0x00]);
+    assert_eq!(cpu.register_x, 1);
+}
+
+#[test]
+fn test_0xd0_bne_taken_and_not_taken() {
+    let mut cpu = CPU::new();
+    // LDX #$01; BNE +1; INX; INX; BRK
+    cpu.run(vec![0xA2, 0x01, 0xD0, 0x01, 0xE8, 0xE8, 0x00]);
+    assert_eq!(cpu.register_x, 2);
+
+    // LDX #$00; BNE +1; INX; INX; BRK
+    cpu.run(vec![0xA2, 0x00, 0xD0, 0x01, 0xE8, 0xE8, 0x00]);
+    assert_eq!(cpu.register_x, 2);
+}
+
+#[test]
+fn test_branch_cycles() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0xF0, 0x00, 0xD0, 0x00, 0x00]);
+    cpu.reset();
+    cpu.status = STATUS_ZERO;
+    assert_eq!(cpu.step(), 3);
+    assert_eq!(cpu.step(), 2);
+
+    // Branching backward from $8002 to $7FFE crosses a page.
+    cpu.load(vec![0xF0, 0xFC]);
+    cpu.status = STATUS_ZERO;
+    assert_eq!(cpu.step(), 4);
+    assert_eq!(cpu.program_counter, 0x7FFE);
+}
+
+#[test]
+fn test_halt_on_jmp_to_self() {
+    let mut cpu = CPU::new();
+    cpu.set_halt_on_jump_to_self(true);
+    // LDA #$01; JMP $8002
+    cpu.run(vec![0xA9, 0x01, 0x4C, 0x02, 0x80]);
+    assert!(cpu.is_halted());
+    assert_eq!(cpu.register_a, 1);
+    assert_eq!(cpu.program_counter, 0x8002);
+}
+
+#[test]
+fn test_halt_on_branch_to_self() {
+    let mut cpu = CPU::new();
+    cpu.set_halt_on_jump_to_self(true);
+    // LDA #$01; BNE *
+    cpu.run(vec![0xA9, 0x01, 0xD0, 0xFE]);
+    assert!(cpu.is_halted());
+    assert_eq!(cpu.program_counter, 0x8002);
+}
+
+#[test]
+fn test_instructions_yields_each_step() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0xA9, 0xC0, 0xAA, 0xE8, 0x00]);
+    cpu.reset();
+
+    let steps: Vec<StepInfo> = cpu.instructions().take(2).collect();
+    assert_eq!(steps.len(), 2);
+    assert_eq!(steps[0].pc, 0x8000);
+    assert_eq!(steps[0].opcode, 0xA9);
+    assert_eq!(steps[0].register_a, 0xC0);
+    assert_eq!(steps[0].status & STATUS_NEGATIVE, STATUS_NEGATIVE);
+    assert_eq!(steps[0].cycles, 2);
+    assert_eq!(steps[1].pc, 0x8002);
+    assert_eq!(steps[1].opcode, 0xAA);
+    assert_eq!(steps[1].register_x, 0xC0);
+
+    let rest: Vec<StepInfo> = cpu.instructions().collect();
+    assert_eq!(rest.len(), 2);
+    assert_eq!(rest[0].register_x, 0xC1);
+    assert_eq!(rest[1].opcode, 0x00);
+    assert!(cpu.is_halted());
+}
+
+#[test]
+fn test_memory_changed_since_reports_store() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0xA9, 0x42, 0x85, 0x10, 0x00]);
+    cpu.reset();
+    let snapshot = cpu.snapshot_memory();
+    cpu.step();
+    cpu.step();
+    assert_eq!(
+        cpu.memory_changed_since(&snapshot),
+        vec![(0x0010, 0x00, 0x42)]
+    );
+}
+
+#[test]
+fn test_0xc9_cmp_negative_flag_from_difference() {
+    let mut cpu = CPU::new();
+    // $80 - $01 = $7F: N clear, C set.
+    cpu.run(vec![0xA9, 0x80, 0xC9, 0x01, 0x00]);
+    assert_eq!(cpu.status & STATUS_NEGATIVE, 0);
+    assert_eq!(cpu.status & STATUS_CARRY, STATUS_CARRY);
+    assert_eq!(cpu.status & STATUS_ZERO, 0);
+    assert_eq!(cpu.register_a, 0x80);
+
+    // $01 - $80 = $81: N set, C clear.
+    cpu.run(vec![0xA9, 0x01, 0xC9, 0x80, 0x00]);
+    assert_eq!(cpu.status & STATUS_NEGATIVE, STATUS_NEGATIVE);
+    assert_eq!(cpu.status & STATUS_CARRY, 0);
+    assert_eq!(cpu.status & STATUS_ZERO, 0);
+}
+
+#[test]
+fn test_0xc9_cmp_equal_values_with_bit_7_set() {
+    let mut cpu = CPU::new();
+    cpu.run(vec![0xA9, 0x90, 0xC9, 0x90, 0x00]);
+    assert_eq!(cpu.status & STATUS_ZERO, STATUS_ZERO);
+    assert_eq!(cpu.status & STATUS_CARRY, STATUS_CARRY);
+    assert_eq!(cpu.status & STATUS_NEGATIVE, 0);
+}
+
+#[test]
+fn test_0xe4_cpx_zero_page() {
+    let mut cpu = CPU::new();
+    cpu.mem_write(0x10, 0x80);
+    cpu.run(vec![0xA2, 0x01, 0xE4, 0x10, 0x00]);
+    assert_eq!(cpu.status & STATUS_NEGATIVE, STATUS_NEGATIVE);
+    assert_eq!(cpu.status & STATUS_CARRY, 0);
+}
+
+#[test]
+fn test_0xc0_cpy_immediate() {
+    let mut cpu = CPU::new();
+    cpu.run(vec![0xA0, 0x80, 0xC0, 0x01, 0x00]);
+    assert_eq!(cpu.status & STATUS_NEGATIVE, 0);
+    assert_eq!(cpu.status & STATUS_CARRY, STATUS_CARRY);
+}
+
+#[test]
+fn test_state_restore_reverts_registers_only() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0xA9, 0x42, 0x85, 0x10, 0x00]);
+    cpu.reset();
+    let before = cpu.state();
+
+    cpu.step();
+    cpu.step();
+    assert_eq!(cpu.register_a, 0x42);
+    assert_ne!(cpu.state(), before);
+
+    cpu.restore(before);
+    assert_eq!(cpu.state(), before);
+    assert_eq!(cpu.register_a, 0);
+    assert_eq!(cpu.program_counter, 0x8000);
+    assert_eq!(cpu.cycles, 0);
+    assert_eq!(cpu.mem_read(0x10), 0x42);
+}
+
+#[test]
+fn test_0x69_adc_carry_and_overflow() {
+    let mut cpu = CPU::new();
+    cpu.run(vec![0xA9, 0x50, 0x69, 0x50, 0x00]);
+    assert_eq!(cpu.register_a, 0xA0);
+    assert_eq!(cpu.status & STATUS_OVERFLOW, STATUS_OVERFLOW);
+    assert_eq!(cpu.status & STATUS_CARRY, 0);
+
+    cpu.run(vec![0xA9, 0xFF, 0x38, 0x69, 0x00, 0x00]);
+    assert_eq!(cpu.register_a, 0x00);
+    assert_eq!(cpu.status & STATUS_CARRY, STATUS_CARRY);
+    assert_eq!(cpu.status & STATUS_ZERO, STATUS_ZERO);
+    assert_eq!(cpu.status & STATUS_OVERFLOW, 0);
+}
+
+#[test]
+fn test_0xe9_sbc_borrow() {
+    let mut cpu = CPU::new();
+    cpu.run(vec![0xA9, 0x05, 0x38, 0xE9, 0x03, 0x00]);
+    assert_eq!(cpu.register_a, 0x02);
+    assert_eq!(cpu.status & STATUS_CARRY, STATUS_CARRY);
+
+    // Without the carry set, an extra 1 is borrowed.
+    cpu.run(vec![0xA9, 0x05, 0x18, 0xE9, 0x05, 0x00]);
+    assert_eq!(cpu.register_a, 0xFF);
+    assert_eq!(cpu.status & STATUS_CARRY, 0);
+    assert_eq!(cpu.status & STATUS_NEGATIVE, STATUS_NEGATIVE);
+}
+
+#[test]
+#[cfg(not(feature = "generic_6502"))]
+fn test_adc_ignores_decimal_flag_on_2a03() {
+    let mut cpu = CPU::new();
+    // SED; CLC; LDA #$09; ADC #$01
+    cpu.run(vec![0xF8, 0x18, 0xA9, 0x09, 0x69, 0x01, 0x00]);
+    assert_eq!(cpu.status & STATUS_DECIMAL_MODE, STATUS_DECIMAL_MODE);
+    assert_eq!(cpu.register_a, 0x0A);
+}
+
+#[test]
+#[cfg(feature = "generic_6502")]
+fn test_adc_decimal_mode_on_generic_6502() {
+    let mut cpu = CPU::new();
+    // SED; CLC; LDA #$09; ADC #$01
+    cpu.run(vec![0xF8, 0x18, 0xA9, 0x09, 0x69, 0x01, 0x00]);
+    assert_eq!(cpu.status & STATUS_DECIMAL_MODE, STATUS_DECIMAL_MODE);
+    assert_eq!(cpu.register_a, 0x10);
+
+    // SED; CLC; LDA #$58; ADC #$46 gives 104: 04 and a carry.
+    cpu.run(vec![0xF8, 0x18, 0xA9, 0x58, 0x69, 0x46, 0x00]);
+    assert_eq!(cpu.register_a, 0x04);
+    assert_eq!(cpu.status & STATUS_CARRY, STATUS_CARRY);
+}
+
+#[test]
+#[cfg(feature = "generic_6502")]
+fn test_sbc_decimal_mode_on_generic_6502() {
+    let mut cpu = CPU::new();
+    // SED; SEC; LDA #$10; SBC #$01
+    cpu.run(vec![0xF8, 0x38, 0xA9, 0x10, 0xE9, 0x01, 0x00]);
+    assert_eq!(cpu.register_a, 0x09);
+    assert_eq!(cpu.status & STATUS_CARRY, STATUS_CARRY);
+
+    // SED; SEC; LDA #$00; SBC #$01 borrows: 99.
+    cpu.run(vec![0xF8, 0x38, 0xA9, 0x00, 0xE9, 0x01, 0x00]);
+    assert_eq!(cpu.register_a, 0x99);
+    assert_eq!(cpu.status & STATUS_CARRY, 0);
+}
+
+#[test]
+#[cfg(feature = "generic_6502")]
+fn test_decimal_mode_cycles() {
+    let penalty = if cfg!(feature = "cmos_65c02") { 1 } else { 0 };
+    let mut cpu = CPU::new();
+    // ADC #$01; SBC #$01; ADC #$01
+    cpu.load(vec![0x69, 0x01, 0xE9, 0x01, 0x69, 0x01, 0x00]);
+    cpu.reset();
+    cpu.status = STATUS_DECIMAL_MODE;
+    assert_eq!(cpu.step(), 2 + penalty);
+    assert_eq!(cpu.step(), 2 + penalty);
+
+    // Binary mode is unaffected.
+    cpu.status = 0;
+    assert_eq!(cpu.step(), 2);
+}
+
+/**
+ * Execute a single decimal mode ADC or SBC immediate and return the
+ * accumulator together with the N, V, Z and C flags.
+ */
+#[cfg(feature = "generic_6502")]
+fn run_decimal(opcode: u8, a: u8, operand: u8, carry_in: bool) -> (u8, u8) {
+    let mut cpu = CPU::new();
+    cpu.load(vec![opcode, operand, 0x00]);
+    cpu.reset();
+    cpu.register_a = a;
+    cpu.status = STATUS_DECIMAL_MODE;
+    if carry_in {
+        cpu.status |= STATUS_CARRY;
+    }
+    cpu.execute();
+    let mask = STATUS_NEGATIVE | STATUS_OVERFLOW | STATUS_ZERO | STATUS_CARRY;
+    (cpu.register_a, cpu.status & mask)
+}
+
+#[test]
+#[cfg(feature = "generic_6502")]
+fn test_adc_decimal_mode_flag_quirks() {
+    const N: u8 = STATUS_NEGATIVE;
+    const V: u8 = STATUS_OVERFLOW;
+    const Z: u8 = STATUS_ZERO;
+    const C: u8 = STATUS_CARRY;
+
+    // 99 + 01 is 00, but Z follows the binary sum and N the unadjusted one.
+    assert_eq!(run_decimal(0x69, 0x99, 0x01, false), (0x00, N | C));
+    assert_eq!(run_decimal(0x69, 0x79, 0x00, true), (0x80, N | V));
+    assert_eq!(run_decimal(0x69, 0x50, 0x50, false), (0x00, N | V | C));
+    assert_eq!(run_decimal(0x69, 0x12, 0x34, false), (0x46, 0));
+    assert_eq!(run_decimal(0x69, 0x00, 0x00, false), (0x00, Z));
+}
+
+#[test]
+#[cfg(feature = "generic_6502")]
+fn test_sbc_decimal_mode_flags_follow_binary_result() {
+    const N: u8 = STATUS_NEGATIVE;
+    const V: u8 = STATUS_OVERFLOW;
+    const Z: u8 = STATUS_ZERO;
+    const C: u8 = STATUS_CARRY;
+
+    assert_eq!(run_decimal(0xE9, 0x00, 0x01, true), (0x99, N));
+    assert_eq!(run_decimal(0xE9, 0x80, 0x01, true), (0x79, V | C));
+    assert_eq!(run_decimal(0xE9, 0x21, 0x21, true), (0x00, Z | C));
+    assert_eq!(run_decimal(0xE9, 0x46, 0x12, false), (0x33, C));
+}
+
+#[test]
+fn test_flags_decodes_status() {
+    let mut cpu = CPU::new();
+    cpu.status = 0b1100_0101;
+    assert_eq!(
+        cpu.flags(),
+        Flags {
+            carry: true,
+            zero: false,
+            interrupt_disable: true,
+            decimal: false,
+            break_flag: false,
+            overflow: true,
+            negative: true,
+        }
+    );
+
+    cpu.status = 0b0001_1010;
+    let flags = cpu.flags();
+    assert!(!flags.carry);
+    assert!(flags.zero);
+    assert!(!flags.interrupt_disable);
+    assert!(flags.decimal);
+    assert!(flags.break_flag);
+    assert!(!flags.overflow);
+    assert!(!flags.negative);
+}
+
+#[test]
+fn test_stack_debug_reports_overflow() {
+    let mut cpu = CPU::new();
+    cpu.set_stack_debug(true);
+    cpu.load(vec![0x08, 0x08, 0x08, 0x00]);
+    cpu.reset();
+    cpu.stack_pointer = 0x01;
+    for _ in 0..3 {
+        cpu.step();
+    }
+
+    assert_eq!(cpu.stack_wraps(), &[StackWrap::Overflow { pc: 0x8001 }]);
+    assert_eq!(cpu.stack_pointer, 0xFE);
+    assert_eq!(cpu.mem_read(STACK), cpu.mem_read(STACK + 0xFF));
+}
+
+#[test]
+fn test_stack_debug_reports_underflow() {
+    let mut cpu = CPU::new();
+    cpu.set_stack_debug(true);
+    cpu.load(vec![0x28, 0x00]);
+    cpu.reset();
+    cpu.stack_pointer = 0xFF;
+    cpu.step();
+
+    assert_eq!(cpu.stack_wraps(), &[StackWrap::Underflow { pc: 0x8000 }]);
+    assert_eq!(cpu.stack_pointer, 0x00);
+}
+
+#[test]
+fn test_stack_wraps_not_recorded_by_default() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0x08, 0x08, 0x00]);
+    cpu.reset();
+    cpu.stack_pointer = 0x00;
+    cpu.step();
+    cpu.step();
+
+    assert!(cpu.stack_wraps().is_empty());
+    assert_eq!(cpu.stack_pointer, 0xFE);
+}
+
+#[test]
+fn test_run_until_stops_before_instruction() {
+    let mut cpu = CPU::new();
+    // loop: INX; JMP loop
+    cpu.load(vec![0xE8, 0x4C, 0x00, 0x80]);
+    cpu.reset();
+    cpu.run_until(|cpu| cpu.register_x == 0x42);
+
+    assert_eq!(cpu.register_x, 0x42);
+    assert_eq!(cpu.program_counter, 0x8001);
+    assert!(!cpu.is_halted());
+}
+
+#[test]
+fn test_run_until_stops_on_halt() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0xE8, 0x00]);
+    cpu.reset();
+    cpu.run_until(|_| false);
+
+    assert!(cpu.is_halted());
+    assert_eq!(cpu.register_x, 1);
+}
+
+#[test]
+fn test_recent_writes_keeps_last_n_in_order() {
+    let mut cpu = CPU::new();
+    // LDA #$01; STA $10; STA $11; STA $12; STA $13; PHP; STA $14; STA $15
+    cpu.load(vec![
+        0xA9, 0x01, 0x85, 0x10, 0x85, 0x11, 0x85, 0x12, 0x85, 0x13, 0x08, 0x85, 0x14, 0x85, 0x15,
+        0x00,
+    ]);
+    cpu.reset();
+    cpu.set_write_log(3);
+    cpu.execute_with_limit(8);
+
+    assert_eq!(
+        cpu.recent_writes(),
+        &[
+            WriteEvent {
+                pc: 0x800A,
+                addr: 0x01FD,
+                value: STATUS_BREAK | STATUS_UNUSED
+            },
+            WriteEvent {
+                pc: 0x800B,
+                addr: 0x0014,
+                value: 0x01
+            },
+            WriteEvent {
+                pc: 0x800D,
+                addr: 0x0015,
+                value: 0x01
+            },
+        ]
+    );
+}
+
+#[test]
+fn test_write_log_with_huge_capacity() {
+    let mut cpu = CPU::new();
+    cpu.set_write_log(usize::MAX);
+    cpu.mem_write(0x10, 0x42);
+    assert_eq!(
+        cpu.recent_writes(),
+        &[WriteEvent {
+            pc: 0x0000,
+            addr: 0x0010,
+            value: 0x42
+        }]
+    );
+}
+
+#[test]
+fn test_recent_writes_disabled_by_default() {
+    let mut cpu = CPU::new();
+    cpu.run(vec![0xA9, 0x01, 0x85, 0x10, 0x00]);
+    assert!(cpu.recent_writes().is_empty());
+}
+
+// (a, operand, carry in) => (result, carry out, overflow)
+type ArithmeticCase = ((u8, u8, bool), (u8, bool, bool));
+
+/**
+ * Execute a single ADC or SBC immediate and return `(result, carry, overflow)`.
+ */
+fn run_arithmetic(opcode: u8, a: u8, operand: u8, carry_in: bool) -> (u8, bool, bool) {
+    let mut cpu = CPU::new();
+    cpu.load(vec![opcode, operand, 0x00]);
+    cpu.reset();
+    cpu.register_a = a;
+    if carry_in {
+        cpu.status |= STATUS_CARRY;
+    }
+    cpu.execute();
+    (
+        cpu.register_a,
+        cpu.status & STATUS_CARRY != 0,
+        cpu.status & STATUS_OVERFLOW != 0,
+    )
+}
+
+#[test]
+fn test_adc_overflow_reference_table() {
+    let table: [ArithmeticCase; 11] = [
+        // positive + positive
+        ((0x50, 0x10, false), (0x60, false, false)),
+        ((0x50, 0x50, false), (0xA0, false, true)),
+        // positive + negative
+        ((0x50, 0x90, false), (0xE0, false, false)),
+        ((0x50, 0xD0, false), (0x20, true, false)),
+        // negative + positive
+        ((0xD0, 0x10, false), (0xE0, false, false)),
+        ((0xD0, 0x50, false), (0x20, true, false)),
+        // negative + negative
+        ((0xD0, 0x90, false), (0x60, true, true)),
+        ((0xD0, 0xD0, false), (0xA0, true, false)),
+        // carry in pushing the result over a boundary
+        ((0x7F, 0x00, true), (0x80, false, true)),
+        ((0xFF, 0x00, true), (0x00, true, false)),
+        ((0x80, 0xFF, true), (0x80, true, false)),
+    ];
+
+    for ((a, operand, carry_in), expected) in table {
+        assert_eq!(
+            run_arithmetic(0x69, a, operand, carry_in),
+            expected,
+            "ADC: A=${:02X} M=${:02X} C={}",
+            a,
+            operand,
+            carry_in
+        );
+    }
+}
+
+#[test]
+fn test_sbc_overflow_reference_table() {
+    let table: [ArithmeticCase; 10] = [
+        // positive - positive
+        ((0x50, 0x30, true), (0x20, true, false)),
+        ((0x50, 0x70, true), (0xE0, false, false)),
+        // positive - negative
+        ((0x50, 0xF0, true), (0x60, false, false)),
+        ((0x50, 0xB0, true), (0xA0, false, true)),
+        // negative - positive
+        ((0xD0, 0x30, true), (0xA0, true, false)),
+        ((0xD0, 0x70, true), (0x60, true, true)),
+        // negative - negative
+        ((0xD0, 0xF0, true), (0xE0, false, false)),
+        ((0xD0, 0xB0, true), (0x20, true, false)),
+        // borrow in pushing the result over a boundary
+        ((0x00, 0x00, false), (0xFF, false, false)),
+        ((0x80, 0x00, false), (0x7F, true, true)),
+    ];
+
+    for ((a, operand, carry_in), expected) in table {
+        assert_eq!(
+            run_arithmetic(0xE9, a, operand, carry_in),
+            expected,
+            "SBC: A=${:02X} M=${:02X} C={}",
+            a,
+            operand,
+            carry_in
+        );
+    }
+}
+
+#[test]
+fn test_cycle_override() {
+    let mut cpu = CPU::new();
+    cpu.set_cycle_override(0xA9, 5);
+    cpu.load(vec![0xA9, 0x01, 0xAA, 0xA9, 0x02, 0x00]);
+    cpu.reset();
+
+    assert_eq!(cpu.step(), 5);
+    assert_eq!(cpu.step(), 2);
+    assert_eq!(cpu.cycles, 7);
+
+    cpu.clear_cycle_overrides();
+    assert_eq!(cpu.step(), 2);
+    assert_eq!(cpu.cycles, 9);
+}
+
+#[test]
+fn test_cycle_override_keeps_branch_penalty() {
+    let mut cpu = CPU::new();
+    cpu.set_cycle_override(0xD0, 4);
+    cpu.load(vec![0xD0, 0x00, 0x00]);
+    cpu.reset();
+    assert_eq!(cpu.step(), 5);
+}
+
+#[test]
+fn test_cycle_override_saturates_with_penalty() {
+    let mut cpu = CPU::new();
+    cpu.set_cycle_override(0xD0, u8::MAX);
+    cpu.load(vec![0xD0, 0x00, 0x00]);
+    cpu.reset();
+    assert_eq!(cpu.step(), u8::MAX);
+}
+
+#[test]
+fn test_access_tracer_captures_bus_traffic() {
+    let mut cpu = CPU::new();
+    cpu.mem_write(0x10, 0x42);
+    // LDA $10; STA $11; BRK
+    cpu.load(vec![0xA5, 0x10, 0x85, 0x11, 0x00]);
+    cpu.reset();
+
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let sink = Rc::clone(&log);
+    cpu.set_access_tracer(move |access| sink.borrow_mut().push(*access));
+    cpu.trace();
+    cpu.execute();
+
+    let read = |pc, addr, value| MemoryAccess {
+        pc,
+        addr,
+        value,
+        kind: AccessKind::Read,
+    };
+    let write = |pc, addr, value| MemoryAccess {
+        pc,
+        addr,
+        value,
+        kind: AccessKind::Write,
+    };
+    assert_eq!(
+        *log.borrow(),
+        vec![
+            read(0x8000, 0x8000, 0xA5),
+            read(0x8000, 0x8001, 0x10),
+            read(0x8000, 0x0010, 0x42),
+            read(0x8002, 0x8002, 0x85),
+            read(0x8002, 0x8003, 0x11),
+            write(0x8002, 0x0011, 0x42),
+            read(0x8004, 0x8004, 0x00),
+            write(0x8004, 0x01FD, 0x80),
+            write(0x8004, 0x01FC, 0x06),
+            write(0x8004, 0x01FB, STATUS_BREAK | STATUS_UNUSED),
+        ]
+    );
+
+    cpu.clear_access_tracer();
+    cpu.mem_read(0x10);
+    assert_eq!(log.borrow().len(), 10);
+}
+
+#[test]
+fn test_self_modifying_code_in_zero_page() {
+    let mut cpu = CPU::new();
+    let code = [
+        0xA9, 0xE8, // LDA #$E8 (the INX opcode)
+        0x85, 0x06, // STA $06
+        0xA2, 0x00, // LDX #$00
+        0x00, // BRK, rewritten to INX before it executes
+        0x00, // BRK
+    ];
+    for (addr, &byte) in code.iter().enumerate() {
+        cpu.mem_write(addr as u16, byte);
+    }
+    cpu.program_counter = 0x0000;
+    cpu.execute();
+
+    assert_eq!(cpu.mem_read(0x06), 0xE8);
+    assert_eq!(cpu.register_x, 1);
+    assert_eq!(cpu.program_counter, 0x0009);
+}
+
+#[test]
+fn test_copy_code_to_ram_and_jump() {
+    let mut cpu = CPU::new();
+    cpu.run(vec![
+        0xA9, 0xE8, // LDA #$E8 (INX)
+        0x8D, 0x00, 0x02, // STA $0200
+        0xA9, 0x00, // LDA #$00 (BRK)
+        0x8D, 0x01, 0x02, // STA $0201
+        0x4C, 0x00, 0x02, // JMP $0200
+    ]);
+
+    assert_eq!(cpu.register_x, 1);
+    assert_eq!(cpu.program_counter, 0x0203);
+}
+
+#[test]
+fn test_run_with_limit_stops_non_terminating_program() {
+    let mut cpu = CPU::new();
+    // loop: INX; JMP loop
+    assert_eq!(
+        cpu.run_with_limit(vec![0xE8, 0x4C, 0x00, 0x80], 10),
+        RunResult::BudgetExhausted
+    );
+    assert!(!cpu.is_halted());
+    assert_eq!(cpu.register_x, 5);
+    assert_eq!(cpu.program_counter, 0x8000);
+}
+
+#[test]
+fn test_run_with_limit_reports_halt() {
+    let mut cpu = CPU::new();
+    assert_eq!(
+        cpu.run_with_limit(vec![0xE8, 0xE8, 0x00], 10),
+        RunResult::Halted
+    );
+    assert_eq!(cpu.register_x, 2);
+
+    // BRK is the third instruction, so a limit of two stops just short.
+    assert_eq!(
+        cpu.run_with_limit(vec![0xE8, 0xE8, 0x00], 2),
+        RunResult::BudgetExhausted
+    );
+}
+
+#[test]
+fn test_page_cross_penalty_only_applies_to_reads() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![
+        0xA2, 0x01, // LDX #$01
+        0xBD, 0x00, 0x02, // LDA $0200,X
+        0xBD, 0xFF, 0x02, // LDA $02FF,X
+        0x9D, 0x00, 0x02, // STA $0200,X
+        0x9D, 0xFF, 0x02, // STA $02FF,X
+        0x00,
+    ]);
+    cpu.reset();
+    cpu.step();
+
+    assert_eq!(cpu.step(), 4);
+    assert_eq!(cpu.step(), 5);
+    assert_eq!(cpu.step(), 5);
+    assert_eq!(cpu.step(), 5);
+    assert_eq!(cpu.mem_read(0x0300), cpu.register_a);
+}
+
+#[test]
+fn test_indirect_y_adds_y_to_pointer() {
+    let mut cpu = CPU::new();
+    cpu.mem_write_u16(0x10, 0x02FF);
+    cpu.mem_write(0x0300, 0x42);
+    cpu.load(vec![
+        0xA0, 0x01, // LDY #$01
+        0xB1, 0x10, // LDA ($10),Y
+        0x91, 0x10, // STA ($10),Y
+        0x00,
+    ]);
+    cpu.reset();
+    cpu.step();
+
+    assert_eq!(cpu.step(), 6);
+    assert_eq!(cpu.register_a, 0x42);
+    assert_eq!(cpu.step(), 6);
+}
+
+#[test]
+fn test_vector_fetch_has_no_read_side_effects() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0x00]);
+
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let sink = Rc::clone(&log);
+    cpu.set_access_tracer(move |access| sink.borrow_mut().push(*access));
+
+    cpu.reset();
+    assert_eq!(cpu.program_counter, 0x8000);
+    assert!(log.borrow().is_empty());
+
+    // Ordinary reads of the vector do reach the bus.
+    assert_eq!(cpu.mem_read(0xFFFC), 0x00);
+    assert_eq!(cpu.mem_read(0xFFFD), 0x80);
+    assert_eq!(log.borrow().len(), 2);
+}
+
+#[test]
+fn test_soft_reset_keeps_registers() {
+    let mut cpu = CPU::new();
+    cpu.run(vec![0xA9, 0x11, 0xA2, 0x22, 0xA0, 0x33, 0xF8, 0x38, 0x00]);
+    let cycles = cpu.cycles;
+    let stack_pointer = cpu.stack_pointer;
+
+    cpu.soft_reset();
+    assert_eq!(cpu.register_a, 0x11);
+    assert_eq!(cpu.register_x, 0x22);
+    assert_eq!(cpu.register_y, 0x33);
+    assert_eq!(cpu.stack_pointer, stack_pointer - 3);
+    assert_eq!(cpu.status, STATUS_CARRY | STATUS_INTERRUPT_DISABLE);
+    assert_eq!(cpu.program_counter, 0x8000);
+    assert_eq!(cpu.cycles, cycles + 7);
+}
+
+#[test]
+fn test_effective_address_of_indexed_store() {
+    let mut cpu = CPU::new();
+    // LDX #$05; STA $0200,X
+    cpu.load(vec![0xA2, 0x05, 0x9D, 0x00, 0x02, 0x00]);
+    cpu.reset();
+    cpu.step();
+
+    assert_eq!(cpu.effective_address(&AddressingMode::AbsoluteX), 0x0205);
+    assert_eq!(cpu.program_counter, 0x8002);
+    assert_eq!(cpu.mem_read(0x0205), 0x00);
+}
+
+#[test]
+fn test_0x29_and_immediate() {
+    let mut cpu = CPU::new();
+    cpu.run(vec![0xA9, 0xF0, 0x29, 0x3C, 0x00]);
+    assert_eq!(cpu.register_a, 0x30);
+
+    cpu.run(vec![0xA9, 0xF0, 0x29, 0x0F, 0x00]);
+    assert_eq!(cpu.status & STATUS_ZERO, STATUS_ZERO);
+}
+
+#[test]
+fn test_asl_accumulator_and_memory() {
+    let mut cpu = CPU::new();
+    // LDA #$81; ASL A
+    cpu.run(vec![0xA9, 0x81, 0x0A, 0x00]);
+    assert_eq!(cpu.register_a, 0x02);
+    assert_eq!(cpu.status & STATUS_CARRY, STATUS_CARRY);
+
+    // ASL $10
+    cpu.mem_write(0x10, 0x40);
+    cpu.run(vec![0x06, 0x10, 0x00]);
+    assert_eq!(cpu.mem_read(0x10), 0x80);
+    assert_eq!(cpu.status & STATUS_CARRY, 0);
+    assert_eq!(cpu.status & STATUS_NEGATIVE, STATUS_NEGATIVE);
+}
+
+#[test]
+fn test_opcode_table_matches_implemented_instructions() {
+    // Every opcode dispatch() implements must have a table entry, and every
+    // table entry must be implemented. Opcodes missing from the table are
+    // dispatched with an arbitrary mode, which only matters if they are
+    // implemented anyway.
+    for code in 0..=0xFFu8 {
+        let mut cpu = CPU::new();
+        cpu.program_counter = 0x8001;
+        let entry = opcodes::CPU_OPCODES_MAP.get(&code);
+        let mode = entry.map_or(AddressingMode::Absolute, |op| op.mode);
+
+        let implemented = cpu.dispatch(code, &mode).is_some();
+        let in_table = entry.is_some();
+        assert_eq!(
+            implemented, in_table,
+            "opcode {:02X}: implemented {}, in table {}",
+            code, implemented, in_table
+        );
+    }
+}
+
+#[test]
+fn test_serial_port_captures_output() {
+    let mut cpu = CPU::new();
+    cpu.set_serial_port(Some(0xF001));
+    // LDA #'H'; STA $F001; LDA #'I'; STA $F001
+    cpu.run(vec![
+        0xA9, 0x48, 0x8D, 0x01, 0xF0, 0xA9, 0x49, 0x8D, 0x01, 0xF0, 0x00,
+    ]);
+    assert_eq!(cpu.serial_output(), "HI");
+    assert_eq!(cpu.mem_read(0xF001), 0x00);
+}
+
+#[test]
+fn test_absolute_x_wraps_at_top_of_memory() {
+    assert!(page_crossed(0xFFFF, 0x0000));
+    assert!(!page_crossed(0xFF00, 0xFFFE));
+
+    let mut cpu = CPU::new();
+    cpu.mem_write(0x0000, 0x42);
+    // LDX #$01; LDA $FFFF,X
+    cpu.load(vec![0xA2, 0x01, 0xBD, 0xFF, 0xFF, 0x00]);
+    cpu.reset();
+    cpu.step();
+
+    assert_eq!(cpu.effective_address(&AddressingMode::AbsoluteX), 0x0000);
+    assert_eq!(cpu.step(), 5);
+    assert_eq!(cpu.register_a, 0x42);
+}
+
+#[test]
+fn test_nop_policy_skips_unknown_opcodes() {
+    let mut cpu = CPU::new();
+    cpu.set_illegal_opcode_policy(IllegalOpcodePolicy::Nop);
+    cpu.load(vec![0xE8, 0x03, 0xE8, 0x00]);
+    cpu.reset();
+
+    cpu.step();
+    assert_eq!(cpu.step(), 2);
+    cpu.execute();
+    assert_eq!(cpu.register_x, 2);
+    assert_eq!(cpu.skipped_opcodes(), &[(0x8001, 0x03)]);
+}
+
+#[test]
+fn test_inc_dec_memory() {
+    let mut cpu = CPU::new();
+    cpu.mem_write(0x10, 0xFF);
+    cpu.mem_write(0x11, 0x01);
+    // INC $10; DEC $11; DEC $11
+    cpu.run(vec![0xE6, 0x10, 0xC6, 0x11, 0xC6, 0x11, 0x00]);
+    assert_eq!(cpu.mem_read(0x10), 0x00);
+    assert_eq!(cpu.mem_read(0x11), 0xFF);
+    assert_eq!(cpu.status & STATUS_NEGATIVE, STATUS_NEGATIVE);
+}
+
+#[test]
+fn test_inc_absolute_x_has_fixed_timing() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![
+        0xA2, 0x01, // LDX #$01
+        0xFE, 0x00, 0x02, // INC $0200,X
+        0xFE, 0xFF, 0x02, // INC $02FF,X
+        0x00,
+    ]);
+    cpu.reset();
+    cpu.step();
+
+    assert_eq!(cpu.step(), 7);
+    assert_eq!(cpu.step(), 7);
+    assert_eq!(cpu.mem_read(0x0201), 1);
+    assert_eq!(cpu.mem_read(0x0300), 1);
+}
+
+#[test]
+fn test_0x00_brk_pushes_return_address_past_padding() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0xE8, 0x00, 0xFF]);
+    cpu.reset();
+    cpu.status = STATUS_CARRY;
+    cpu.execute();
+
+    assert!(cpu.is_halted());
+    assert_eq!(cpu.program_counter, 0x8003);
+    assert_eq!(cpu.stack_pointer, STACK_RESET - 3);
+    assert_eq!(cpu.peek_u16(STACK + STACK_RESET as u16 - 1), 0x8003);
+    assert_eq!(
+        cpu.mem_read(STACK + STACK_RESET as u16 - 2),
+        STATUS_CARRY | STATUS_BREAK | STATUS_UNUSED
+    );
+    assert_eq!(
+        cpu.status & STATUS_INTERRUPT_DISABLE,
+        STATUS_INTERRUPT_DISABLE
+    );
+}
+
+#[test]
+fn test_interrupts_jump_through_vectors() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0x00]);
+    cpu.set_reset_vector(0x9000);
+    cpu.set_nmi_vector(0xA000);
+    cpu.set_irq_vector(0xB000);
+    assert_eq!(cpu.reset_vector(), 0x9000);
+    assert_eq!(cpu.nmi_vector(), 0xA000);
+    assert_eq!(cpu.irq_vector(), 0xB000);
+
+    cpu.reset();
+    assert_eq!(cpu.program_counter, 0x9000);
+
+    cpu.status = STATUS_CARRY;
+    cpu.nmi();
+    assert_eq!(cpu.program_counter, 0xA000);
+    assert_eq!(cpu.stack_pointer, STACK_RESET - 3);
+    assert_eq!(cpu.peek_u16(STACK + STACK_RESET as u16 - 1), 0x9000);
+    assert_eq!(
+        cpu.mem_read(STACK + STACK_RESET as u16 - 2),
+        STATUS_CARRY | STATUS_UNUSED
+    );
+
+    // The NMI disabled interrupts, so the IRQ is ignored until they are
+    // enabled again.
+    cpu.irq();
+    assert_eq!(cpu.program_counter, 0xA000);
+    cpu.status &= !STATUS_INTERRUPT_DISABLE;
+    cpu.irq();
+    assert_eq!(cpu.program_counter, 0xB000);
+    assert_eq!(cpu.cycles, 14);
+}
+
+#[test]
+fn test_register_accessors() {
+    let mut cpu = CPU::new();
+    cpu.set_a(0x00);
+    cpu.set_x(0x80);
+    cpu.set_y(0x33);
+    cpu.set_sp(0xF0);
+    cpu.set_pc(0x1234);
+
+    assert_eq!(cpu.a(), 0x00);
+    assert_eq!(cpu.x(), 0x80);
+    assert_eq!(cpu.y(), 0x33);
+    assert_eq!(cpu.sp(), 0xF0);
+    assert_eq!(cpu.pc(), 0x1234);
+    assert_eq!(cpu.register_x, 0x80);
+
+    // Setting registers does not touch the flags.
+    assert_eq!(cpu.status, 0);
+}
+
+#[test]
+fn test_load_memory_copies_table() {
+    let mut cpu = CPU::new();
+    cpu.load_memory(0x0010, &[0x11, 0x22, 0x33, 0x44]).unwrap();
+    assert_eq!(cpu.mem_read(0x0010), 0x11);
+    assert_eq!(cpu.mem_read(0x0013), 0x44);
+
+    // LDX #$02; LDA $10,X
+    cpu.run(vec![0xA2, 0x02, 0xB5, 0x10, 0x00]);
+    assert_eq!(cpu.register_a, 0x33);
+
+    cpu.load_memory(0xFFFE, &[0xAA, 0xBB]).unwrap();
+    assert_eq!(
+        cpu.load_memory(0xFFFE, &[0x01, 0x02, 0x03]),
+        Err(LoadError::DoesNotFit {
+            addr: 0xFFFE,
+            len: 3
+        })
+    );
+    assert_eq!(cpu.mem_read(0xFFFE), 0xAA);
+}
+
+#[test]
+fn test_step_back_restores_checkpoints() {
+    let mut cpu = CPU::new();
+    cpu.set_undo_depth(2);
+    // LDA #$42; STA $10; INX; BRK
+    cpu.load(vec![0xA9, 0x42, 0x85, 0x10, 0xE8, 0x00]);
+    cpu.reset();
+
+    let mut checkpoints = vec![cpu.state()];
+    for _ in 0..3 {
+        cpu.step();
+        checkpoints.push(cpu.state());
+    }
+    assert_eq!(cpu.mem_read(0x10), 0x42);
+
+    assert!(cpu.step_back());
+    assert_eq!(cpu.state(), checkpoints[2]);
+    assert!(cpu.step_back());
+    assert_eq!(cpu.state(), checkpoints[1]);
+    assert_eq!(cpu.mem_read(0x10), 0x00);
+
+    // Only two instructions were kept.
+    assert!(!cpu.step_back());
+    assert_eq!(cpu.state(), checkpoints[1]);
+
+    cpu.step();
+    assert_eq!(cpu.state(), checkpoints[2]);
+    assert_eq!(cpu.mem_read(0x10), 0x42);
+}
+
+#[test]
+fn test_relative_offsets_are_sign_extended() {
+    let mut cpu = CPU::new();
+    let target = |cpu: &mut CPU, offset: u8| {
+        cpu.mem_write(0x8001, offset);
+        cpu.get_absolute_address(&AddressingMode::Relative, 0x8001)
+    };
+
+    // Offsets are relative to the instruction following the branch.
+    assert_eq!(target(&mut cpu, 0x00), 0x8002);
+    assert_eq!(target(&mut cpu, 0xFE), 0x8000);
+    assert_eq!(target(&mut cpu, 0x7F), 0x8081);
+    assert_eq!(target(&mut cpu, 0x80), 0x7F82);
+
+    // LDX #$FD; loop: INX; BNE loop
+    cpu.load(vec![0xA2, 0xFD, 0xE8, 0xD0, 0xFD, 0x00]);
+    cpu.reset();
+    cpu.execute();
+    assert_eq!(cpu.register_x, 0x00);
+}
+
+#[test]
+fn test_breakpoint_after_hits() {
+    let mut cpu = CPU::new();
+    // LDX #$00; loop: INX; CPX #$05; BNE loop; BRK
+    cpu.load(vec![0xA2, 0x00, 0xE8, 0xE0, 0x05, 0xD0, 0xFB, 0x00]);
+    cpu.reset();
+    cpu.add_breakpoint_after(0x8002, 3);
+    cpu.add_breakpoint(0x8007);
+
+    assert_eq!(cpu.run_to_breakpoint(), RunResult::BreakpointHit(0x8002));
+    assert_eq!(cpu.register_x, 2);
+    assert_eq!(cpu.breakpoint_hits(0x8002), Some(3));
+
+    // Once reached, the breakpoint stops every later iteration too.
+    assert_eq!(cpu.run_to_breakpoint(), RunResult::BreakpointHit(0x8002));
+    assert_eq!(cpu.register_x, 3);
+
+    cpu.remove_breakpoint(0x8002);
+    assert_eq!(cpu.breakpoint_hits(0x8002), None);
+    assert_eq!(cpu.run_to_breakpoint(), RunResult::BreakpointHit(0x8007));
+    assert_eq!(cpu.register_x, 5);
+    assert_eq!(cpu.run_to_breakpoint(), RunResult::Halted);
+    assert!(cpu.is_halted());
+}
+
+#[test]
+fn test_zero_page_word_reads_wrap() {
+    let mut cpu = CPU::new();
+    cpu.mem_write(0x00FF, 0x34);
+    cpu.mem_write(0x0000, 0x12);
+    cpu.mem_write(0x0100, 0x56);
+
+    assert_eq!(cpu.peek_u16_zp_wrap(0xFF), 0x1234);
+    assert_eq!(cpu.peek_u16(0x00FF), 0x5634);
+
+    cpu.mem_write(0x1234, 0x42);
+    cpu.mem_write(0x1235, 0x43);
+    // LDA ($FF),Y
+    cpu.run(vec![0xA0, 0x01, 0xB1, 0xFF, 0x00]);
+    assert_eq!(cpu.register_a, 0x43);
+    // LDX #$01; LDA ($FE,X)
+    cpu.run(vec![0xA2, 0x01, 0xA1, 0xFE, 0x00]);
+    assert_eq!(cpu.register_a, 0x42);
+}
+
+#[test]
+#[cfg(feature = "illegal_opcodes")]
+fn test_unstable_opcodes_with_magic_constant() {
+    let mut cpu = CPU::new();
+    cpu.set_unstable_opcodes(UnstableOpcodes::Magic(0x00));
+    // LDA #$F0; LDX #$3C; ANE #$FF
+    cpu.run(vec![0xA9, 0xF0, 0xA2, 0x3C, 0x8B, 0xFF, 0x00]);
+    assert_eq!(cpu.register_a, 0x30);
+
+    cpu.set_unstable_opcodes(UnstableOpcodes::Magic(0xFF));
+    // LDA #$00; LXA #$0F
+    cpu.run(vec![0xA9, 0x00, 0xAB, 0x0F, 0x00]);
+    assert_eq!(cpu.register_a, 0x0F);
+    assert_eq!(cpu.register_x, 0x0F);
+}
+
+#[test]
+#[cfg(feature = "illegal_opcodes")]
+fn test_unstable_opcodes_are_reproducible_from_seed() {
+    // LDA #$00; LXA #$FF; LDA #$00; LXA #$FF
+    let program = vec![0xA9, 0x00, 0xAB, 0xFF, 0xA9, 0x00, 0xAB, 0xFF, 0x00];
+    let run = |seed| {
+        let mut cpu = CPU::new();
+        cpu.set_unstable_opcodes(UnstableOpcodes::Seeded(seed));
+        cpu.load(program.clone());
+        cpu.reset();
+        cpu.step();
+        cpu.step();
+        let first = cpu.register_a;
+        cpu.execute();
+        (first, cpu.register_a)
+    };
+
+    assert_eq!(run(42), run(42));
+    assert_ne!(run(42), run(43));
+}
+
+#[test]
+fn test_execution_wraps_at_end_of_memory() {
+    let mut cpu = CPU::new();
+    // LDA #$42 straddling the end of memory, followed by INX at $0001.
+    cpu.mem_write(0xFFFF, 0xA9);
+    cpu.mem_write(0x0000, 0x42);
+    cpu.mem_write(0x0001, 0xE8);
+    cpu.program_counter = 0xFFFF;
+
+    cpu.step();
+    assert_eq!(cpu.register_a, 0x42);
+    assert_eq!(cpu.program_counter, 0x0001);
+    cpu.step();
+    assert_eq!(cpu.program_counter, 0x0002);
+
+    cpu.program_counter = 0xFFFF;
+    cpu.mem_write(0xFFFF, 0xE8);
+    cpu.step();
+    assert_eq!(cpu.program_counter, 0x0000);
+}
+
+#[test]
+fn test_word_write_wraps_at_end_of_memory() {
+    let mut cpu = CPU::new();
+    cpu.mem_write_u16(0xFFFF, 0x1234);
+    assert_eq!(cpu.mem_read(0xFFFF), 0x34);
+    assert_eq!(cpu.mem_read(0x0000), 0x12);
+    assert_eq!(cpu.peek_u16(0xFFFF), 0x1234);
+}
+
+#[test]
+fn test_try_load_rejects_oversized_program() {
+    let mut cpu = CPU::new();
+    assert_eq!(
+        cpu.try_load(vec![0xEA; 0x8001]),
+        Err(LoadError::DoesNotFit {
+            addr: 0x8000,
+            len: 0x8001
+        })
+    );
+    assert_eq!(cpu.mem_read(0x8000), 0x00);
+    assert!(cpu.try_load(vec![0xE8; 0x8000]).is_ok());
+}
+
+#[test]
+fn test_lda_sta_cycles() {
+    // (instruction, X, Y, expected cycles). The pointer at $20 is $10F0.
+    let cases: &[(&[u8], u8, u8, u8)] = &[
+        (&[0xA9, 0x01], 0, 0, 2),          // LDA #$01
+        (&[0xA5, 0x10], 0, 0, 3),          // LDA $10
+        (&[0xB5, 0x10], 0xFF, 0, 4),       // LDA $10,X
+        (&[0xAD, 0x00, 0x10], 0, 0, 4),    // LDA $1000
+        (&[0xBD, 0x00, 0x10], 0x0F, 0, 4), // LDA $1000,X
+        (&[0xBD, 0xF0, 0x10], 0x10, 0, 5), // LDA $10F0,X crossing
+        (&[0xB9, 0x00, 0x10], 0, 0x0F, 4), // LDA $1000,Y
+        (&[0xB9, 0xF0, 0x10], 0, 0x10, 5), // LDA $10F0,Y crossing
+        (&[0xA1, 0x1E], 0x02, 0, 6),       // LDA ($1E,X)
+        (&[0xB1, 0x20], 0, 0x0F, 5),       // LDA ($20),Y
+        (&[0xB1, 0x20], 0, 0x10, 6),       // LDA ($20),Y crossing
+        (&[0x85, 0x10], 0, 0, 3),          // STA $10
+        (&[0x95, 0x10], 0xFF, 0, 4),       // STA $10,X
+        (&[0x8D, 0x00, 0x10], 0, 0, 4),    // STA $1000
+        (&[0x9D, 0x00, 0x10], 0x0F, 0, 5), // STA $1000,X
+        (&[0x9D, 0xF0, 0x10], 0x10, 0, 5), // STA $10F0,X crossing
+        (&[0x99, 0x00, 0x10], 0, 0x0F, 5), // STA $1000,Y
+        (&[0x99, 0xF0, 0x10], 0, 0x10, 5), // STA $10F0,Y crossing
+        (&[0x81, 0x1E], 0x02, 0, 6),       // STA ($1E,X)
+        (&[0x91, 0x20], 0, 0x0F, 6),       // STA ($20),Y
+        (&[0x91, 0x20], 0, 0x10, 6),       // STA ($20),Y crossing
+    ];
+
+    for &(program, x, y, expected) in cases {
+        let mut cpu = CPU::new();
+        cpu.load(program.to_vec());
+        cpu.reset();
+        cpu.mem_write_u16(0x20, 0x10F0);
+        cpu.register_x = x;
+        cpu.register_y = y;
+        assert_eq!(cpu.step(), expected, "{:02X?}", program);
+    }
+}
+
+#[test]
+#[cfg(feature = "illegal_opcodes")]
+fn test_run_reports_jam() {
+    let mut cpu = CPU::new();
+    // INX; JAM; INX
+    assert_eq!(cpu.run(vec![0xE8, 0x02, 0xE8]), RunResult::Jammed);
+    assert!(cpu.is_jammed());
+    assert!(cpu.is_halted());
+    assert_eq!(cpu.register_x, 1);
+    assert_eq!(cpu.program_counter, 0x8001);
+
+    // Resuming jams again, while BRK is still a clean halt.
+    assert_eq!(cpu.execute(), RunResult::Jammed);
+    assert_eq!(cpu.run(vec![0xE8, 0x00]), RunResult::Halted);
+    assert!(!cpu.is_jammed());
+}
+
+#[test]
+fn test_reset_cycles_counts_from_zero() {
+    let mut cpu = CPU::new();
+    // LDA #$01; LDA $10; INX
+    cpu.load(vec![0xA9, 0x01, 0xA5, 0x10, 0xE8, 0x00]);
+    cpu.reset();
+    cpu.step();
+    assert_eq!(cpu.cycles, 2);
+
+    cpu.reset_cycles();
+    assert_eq!(cpu.cycles, 0);
+    assert_eq!(cpu.register_a, 0x01);
+    assert_eq!(cpu.program_counter, 0x8002);
+
+    cpu.step();
+    cpu.step();
+    assert_eq!(cpu.cycles, 5);
+}
+
+#[test]
+#[cfg(not(feature = "illegal_opcodes"))]
+fn test_unofficial_opcodes_can_be_compiled_out() {
+    let mut cpu = CPU::new();
+    assert_eq!(
+        cpu.run(vec![0xA7, 0x10, 0x00]),
+        RunResult::UnknownOpcode(0xA7)
+    );
+    assert_eq!(cpu.program_counter, 0x8000);
+}
+
+#[test]
+fn test_run_stops_at_unknown_opcode() {
+    let mut cpu = CPU::new();
+    // INX; $FF
+    assert_eq!(cpu.run(vec![0xE8, 0xFF]), RunResult::UnknownOpcode(0xFF));
+    assert_eq!(cpu.register_x, 1);
+    assert_eq!(cpu.program_counter, 0x8001);
+    assert!(cpu.is_halted());
+
+    // Resuming reports the same opcode again.
+    assert_eq!(cpu.execute(), RunResult::UnknownOpcode(0xFF));
+}
+
+#[test]
+fn test_step_instruction_at_executes_one_instruction() {
+    let mut cpu = CPU::new();
+    cpu.register_a = 0x42;
+    cpu.mem_write(0x0300, 0xAA);
+
+    let (cycles, state) = cpu.step_instruction_at(0x0300);
+    assert_eq!(cycles, 2);
+    assert_eq!(state.x, 0x42);
+    assert_eq!(state.pc, 0x0301);
+}
+
+#[test]
+fn test_cpu_state_is_hashable() {
+    use std::collections::HashSet;
+
+    let mut cpu = CPU::new();
+    let mut states = HashSet::new();
+    states.insert(cpu.state());
+    states.insert(cpu.state());
+    assert_eq!(states.len(), 1);
+
+    cpu.register_a = 0x01;
+    states.insert(cpu.state());
+    assert_eq!(states.len(), 2);
+}
+
+#[test]
+fn test_current_addressing_mode() {
+    let mut cpu = CPU::new();
+    // LDA $0200,X
+    cpu.load(vec![0xBD, 0x00, 0x02, 0x03]);
+    cpu.reset();
+    assert_eq!(
+        cpu.current_addressing_mode(),
+        Some(&AddressingMode::AbsoluteX)
+    );
+
+    cpu.program_counter = 0x8003;
+    assert_eq!(cpu.current_addressing_mode(), None);
+}
+
+#[test]
+fn test_patch_overrides_reads() {
+    let mut cpu = CPU::new();
+    cpu.add_patch(0x10, 0x63);
+    // LDA #$01; STA $10; LDX $10; INC $10; LDY $10
+    cpu.run(vec![
+        0xA9, 0x01, 0x85, 0x10, 0xA6, 0x10, 0xE6, 0x10, 0xA4, 0x10, 0x00,
+    ]);
+    assert_eq!(cpu.register_x, 0x63);
+    assert_eq!(cpu.register_y, 0x63);
+    assert_eq!(cpu.mem_read(0x10), 0x63);
+
+    cpu.remove_patch(0x10);
+    assert_eq!(cpu.mem_read(0x10), 0x64);
+}
+
+#[test]
+fn test_last_instruction_cycles_in_callback() {
+    let mut cpu = CPU::new();
+    let mut timings = Vec::new();
+    // LDA #$01; LDA $10; INC $10
+    let result = cpu.run_with_callback(vec![0xA9, 0x01, 0xA5, 0x10, 0xE6, 0x10, 0x00], |cpu| {
+        timings.push(cpu.last_instruction_cycles())
+    });
+    assert_eq!(result, RunResult::Halted);
+    assert_eq!(timings, vec![2, 3, 5, 7]);
+    assert_eq!(cpu.last_instruction_cycles(), 7);
+}
+
+#[test]
+fn test_brk_without_reset_does_not_wrap_stack() {
+    let mut cpu = CPU::new();
+    cpu.set_stack_debug(true);
+    cpu.load(vec![0xE8, 0x00]);
+    cpu.execute();
+    assert_eq!(cpu.stack_pointer, STACK_RESET - 3);
+    assert!(cpu.stack_wraps().is_empty());
+}
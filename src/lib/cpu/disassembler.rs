@@ -0,0 +1,127 @@
+/*!
+ * Disassembly of 6502 machine code back into assembler syntax.
+ *
+ * Operands are written in the syntax accepted by the assembler, so that a
+ * listing reads like source code. Bytes that are not a known opcode are
+ * listed as `.byte` data, and unofficial opcodes are marked with a `*`
+ * before the mnemonic, as in traces.
+ */
+
+#[cfg(test)]
+#[path = "disassembler_test.rs"]
+mod disassembler_test;
+
+use crate::cpu::{AddressingMode, CPU};
+use crate::opcodes;
+use std::io::{self, Write};
+
+impl CPU {
+    /**
+     * Disassemble the instruction at an address.
+     *
+     * Memory is read with `peek()`, so disassembling has no side effects.
+     *
+     * @param addr The address of the instruction.
+     * @return The instruction in assembler syntax, e.g. `LDA $10,X`, and
+     *         its length in bytes.
+     */
+    pub fn disassemble(&self, addr: u16) -> (String, u16) {
+        let code = self.peek(addr);
+        let info = match opcodes::CPU_OPCODES_MAP.get(&code) {
+            Some(info) => info,
+            None => return (format!(".byte ${:02X}", code), 1),
+        };
+
+        let marker = if info.official { "" } else { "*" };
+        let operand = self.operand_syntax(info, addr.wrapping_add(1));
+        let text = if operand.is_empty() {
+            format!("{}{}", marker, info.instruction)
+        } else {
+            format!("{}{} {}", marker, info.instruction, operand)
+        };
+        (text, info.length as u16)
+    }
+
+    /**
+     * Disassemble every instruction from one address up to and including
+     * another.
+     *
+     * @param start The address of the first instruction.
+     * @param end   The last address to list. An instruction starting at or
+     *              before it is listed in full, even if it runs past it, so
+     *              `0xFFFF` lists up to the end of memory.
+     * @return The address and text of each instruction, in order.
+     */
+    pub fn disassemble_range(&self, start: u16, end: u16) -> Vec<(u16, String)> {
+        self.listing(start, end).collect()
+    }
+
+    /**
+     * Write a listing of every instruction from one address up to another,
+     * one line at a time, e.g. for dumping all of PRG-ROM.
+     *
+     * Each line holds the address and the instruction, e.g.
+     * `8000  LDX #$00`.
+     *
+     * @param start The address of the first instruction.
+     * @param end   The last address to list, as for `disassemble_range()`.
+     * @param w     Where to write the listing.
+     * @return Any error from writing.
+     */
+    pub fn disassemble_to_writer<W: Write>(
+        &self,
+        start: u16,
+        end: u16,
+        w: &mut W,
+    ) -> io::Result<()> {
+        for (addr, text) in self.listing(start, end) {
+            writeln!(w, "{:04X}  {}", addr, text)?;
+        }
+        Ok(())
+    }
+
+    /**
+     * Walk the instructions from one address up to and including another,
+     * disassembling each in turn.
+     *
+     * The walk is done in 32 bits so that it can end at `$FFFF`.
+     */
+    fn listing(&self, start: u16, end: u16) -> impl Iterator<Item = (u16, String)> + '_ {
+        let mut addr = start as u32;
+        std::iter::from_fn(move || {
+            if addr > end as u32 {
+                return None;
+            }
+            let (text, length) = self.disassemble(addr as u16);
+            let line = (addr as u16, text);
+            addr += length as u32;
+            Some(line)
+        })
+    }
+
+    /**
+     * Format an instruction's operand in assembler syntax.
+     *
+     * @param info The opcode being formatted.
+     * @param pos  The position in memory of the instruction's operand.
+     */
+    fn operand_syntax(&self, info: &opcodes::OpCode, pos: u16) -> String {
+        match info.mode {
+            AddressingMode::NoneAddressing => String::new(),
+            AddressingMode::Accumulator => String::from("A"),
+            AddressingMode::Immediate => format!("#${:02X}", self.peek(pos)),
+            AddressingMode::ZeroPage => format!("${:02X}", self.peek(pos)),
+            AddressingMode::ZeroPageX => format!("${:02X},X", self.peek(pos)),
+            AddressingMode::ZeroPageY => format!("${:02X},Y", self.peek(pos)),
+            AddressingMode::Absolute => format!("${:04X}", self.peek_u16(pos)),
+            AddressingMode::AbsoluteX => format!("${:04X},X", self.peek_u16(pos)),
+            AddressingMode::AbsoluteY => format!("${:04X},Y", self.peek_u16(pos)),
+            AddressingMode::Indirect => format!("(${:04X})", self.peek_u16(pos)),
+            AddressingMode::IndirectX => format!("(${:02X},X)", self.peek(pos)),
+            AddressingMode::IndirectY => format!("(${:02X}),Y", self.peek(pos)),
+            AddressingMode::Relative => {
+                format!("${:04X}", self.get_absolute_address(&info.mode, pos))
+            }
+        }
+    }
+}
@@ -0,0 +1,80 @@
+/**
+ * Unit tests for the disassembler.
+ */
+use crate::assembler::assemble;
+use crate::cpu::CPU;
+
+#[test]
+#[cfg(feature = "illegal_opcodes")]
+fn test_disassemble_range_lists_program() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![
+        0xA2, 0x00, // LDX #$00
+        0xBD, 0x00, 0x02, // LDA $0200,X
+        0xB1, 0x10, // LDA ($10),Y
+        0x0A, // ASL A
+        0xD0, 0xF6, // BNE $8000
+        0xA7, 0x10, // LAX $10
+        0x03, // not an opcode
+        0x00, 0x00, // BRK
+    ]);
+
+    assert_eq!(
+        cpu.disassemble_range(0x8000, 0x800E),
+        vec![
+            (0x8000, String::from("LDX #$00")),
+            (0x8002, String::from("LDA $0200,X")),
+            (0x8005, String::from("LDA ($10),Y")),
+            (0x8007, String::from("ASL A")),
+            (0x8008, String::from("BNE $8000")),
+            (0x800A, String::from("*LAX $10")),
+            (0x800C, String::from(".byte $03")),
+            (0x800D, String::from("BRK")),
+        ]
+    );
+}
+
+#[test]
+fn test_disassembly_reassembles() {
+    let source = "LDA #$05\nSTA $10,X\nJMP ($FFFC)\nCMP ($20,X)\nTAX";
+    let program = assemble(source).unwrap();
+    let mut cpu = CPU::new();
+    cpu.load(program.clone());
+
+    let listing: Vec<String> = cpu
+        .disassemble_range(0x8000, 0x8000 + program.len() as u16 - 1)
+        .into_iter()
+        .map(|(_, text)| text)
+        .collect();
+    assert_eq!(listing.join("\n"), source);
+}
+
+#[test]
+fn test_disassemble_to_writer() {
+    let mut cpu = CPU::new();
+    cpu.load(assemble("LDX #$00\nloop: INX\nBNE loop\nBRK").unwrap());
+
+    let mut out = Vec::new();
+    cpu.disassemble_to_writer(0x8000, 0x8004, &mut out).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "8000  LDX #$00\n8002  INX\n8003  BNE $8002\n"
+    );
+}
+
+#[test]
+fn test_disassemble_range_reaches_end_of_memory() {
+    let mut cpu = CPU::new();
+    // Leaves only the reset vector, $8000, in the vectors.
+    cpu.load(vec![]);
+
+    assert_eq!(
+        cpu.disassemble_range(0xFFF9, 0xFFFF),
+        vec![
+            (0xFFF9, String::from("BRK")),
+            (0xFFFB, String::from("BRK")),
+            (0xFFFD, String::from(".byte $80")),
+            (0xFFFE, String::from("BRK")),
+        ]
+    );
+}
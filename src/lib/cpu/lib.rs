@@ -4,5 +4,8 @@
 #[macro_use]
 extern crate lazy_static;
 
+pub mod assembler;
 pub mod cpu;
+pub mod disassembler;
 pub mod opcodes;
+pub mod trace;
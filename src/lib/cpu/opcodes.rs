@@ -4,12 +4,18 @@
 use crate::cpu::AddressingMode;
 use std::collections::HashMap;
 
+#[cfg(test)]
+#[path = "opcodes_test.rs"]
+mod opcodes_test;
+
 pub struct OpCode {
     pub code: u8,
     pub instruction: &'static str,
     pub length: u8,
     pub cycles: u8,
     pub mode: AddressingMode,
+    /** Whether the opcode is part of the documented instruction set. */
+    pub official: bool,
 }
 
 impl OpCode {
@@ -26,13 +32,30 @@ impl OpCode {
             length,
             cycles,
             mode,
+            official: true,
+        }
+    }
+
+    /**
+     * Describe one of the undocumented opcodes of the NMOS 6502.
+     */
+    pub fn unofficial(
+        code: u8,
+        instruction: &'static str,
+        length: u8,
+        cycles: u8,
+        mode: AddressingMode,
+    ) -> Self {
+        OpCode {
+            official: false,
+            ..OpCode::new(code, instruction, length, cycles, mode)
         }
     }
 }
 
 lazy_static! {
     pub static ref CPU_OPCODES: Vec<OpCode> = vec![
-        OpCode::new(0x00, "BRK", 1, 7, AddressingMode::NoneAddressing),
+        OpCode::new(0x00, "BRK", 2 /* (opcode and a padding byte) */, 7, AddressingMode::NoneAddressing),
 
         OpCode::new(0x69, "ADC", 2, 2, AddressingMode::Immediate),
         OpCode::new(0x65, "ADC", 2, 3, AddressingMode::ZeroPage),
@@ -58,7 +81,93 @@ lazy_static! {
         OpCode::new(0x0E, "ASL", 3, 6, AddressingMode::Absolute),
         OpCode::new(0x1E, "ASL", 3, 7, AddressingMode::AbsoluteX),
 
-        OpCode::new(0xE8, "INX", 1, 7, AddressingMode::NoneAddressing),
+        OpCode::new(0x90, "BCC", 2, 2 /* (+1 if branch succeeds, +2 if to a new page) */, AddressingMode::Relative),
+        OpCode::new(0xB0, "BCS", 2, 2 /* (+1 if branch succeeds, +2 if to a new page) */, AddressingMode::Relative),
+        OpCode::new(0xF0, "BEQ", 2, 2 /* (+1 if branch succeeds, +2 if to a new page) */, AddressingMode::Relative),
+        OpCode::new(0x30, "BMI", 2, 2 /* (+1 if branch succeeds, +2 if to a new page) */, AddressingMode::Relative),
+        OpCode::new(0xD0, "BNE", 2, 2 /* (+1 if branch succeeds, +2 if to a new page) */, AddressingMode::Relative),
+        OpCode::new(0x10, "BPL", 2, 2 /* (+1 if branch succeeds, +2 if to a new page) */, AddressingMode::Relative),
+        OpCode::new(0x50, "BVC", 2, 2 /* (+1 if branch succeeds, +2 if to a new page) */, AddressingMode::Relative),
+        OpCode::new(0x70, "BVS", 2, 2 /* (+1 if branch succeeds, +2 if to a new page) */, AddressingMode::Relative),
+
+        OpCode::new(0x18, "CLC", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0xD8, "CLD", 1, 2, AddressingMode::NoneAddressing),
+
+        OpCode::new(0xC9, "CMP", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xC5, "CMP", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0xD5, "CMP", 2, 4, AddressingMode::ZeroPageX),
+        OpCode::new(0xCD, "CMP", 3, 4, AddressingMode::Absolute),
+        OpCode::new(0xDD, "CMP", 3, 4 /* (+1 if page crossed) */, AddressingMode::AbsoluteX),
+        OpCode::new(0xD9, "CMP", 3, 4 /* (+1 if page crossed) */, AddressingMode::AbsoluteY),
+        OpCode::new(0xC1, "CMP", 2, 6, AddressingMode::IndirectX),
+        OpCode::new(0xD1, "CMP", 2, 5 /* (+1 if page crossed) */, AddressingMode::IndirectY),
+
+        OpCode::new(0xE0, "CPX", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xE4, "CPX", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0xEC, "CPX", 3, 4, AddressingMode::Absolute),
+
+        OpCode::new(0xC0, "CPY", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xC4, "CPY", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0xCC, "CPY", 3, 4, AddressingMode::Absolute),
+
+        OpCode::new(0xC6, "DEC", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0xD6, "DEC", 2, 6, AddressingMode::ZeroPageX),
+        OpCode::new(0xCE, "DEC", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0xDE, "DEC", 3, 7, AddressingMode::AbsoluteX),
+
+        OpCode::new(0xE6, "INC", 2, 5, AddressingMode::ZeroPage),
+        OpCode::new(0xF6, "INC", 2, 6, AddressingMode::ZeroPageX),
+        OpCode::new(0xEE, "INC", 3, 6, AddressingMode::Absolute),
+        OpCode::new(0xFE, "INC", 3, 7, AddressingMode::AbsoluteX),
+
+        OpCode::new(0xE8, "INX", 1, 2, AddressingMode::NoneAddressing),
+
+        #[cfg(feature = "illegal_opcodes")]
+        OpCode::unofficial(0x02, "JAM", 1, 2, AddressingMode::NoneAddressing),
+        #[cfg(feature = "illegal_opcodes")]
+        OpCode::unofficial(0x12, "JAM", 1, 2, AddressingMode::NoneAddressing),
+        #[cfg(feature = "illegal_opcodes")]
+        OpCode::unofficial(0x22, "JAM", 1, 2, AddressingMode::NoneAddressing),
+        #[cfg(feature = "illegal_opcodes")]
+        OpCode::unofficial(0x32, "JAM", 1, 2, AddressingMode::NoneAddressing),
+        #[cfg(feature = "illegal_opcodes")]
+        OpCode::unofficial(0x42, "JAM", 1, 2, AddressingMode::NoneAddressing),
+        #[cfg(feature = "illegal_opcodes")]
+        OpCode::unofficial(0x52, "JAM", 1, 2, AddressingMode::NoneAddressing),
+        #[cfg(feature = "illegal_opcodes")]
+        OpCode::unofficial(0x62, "JAM", 1, 2, AddressingMode::NoneAddressing),
+        #[cfg(feature = "illegal_opcodes")]
+        OpCode::unofficial(0x72, "JAM", 1, 2, AddressingMode::NoneAddressing),
+        #[cfg(feature = "illegal_opcodes")]
+        OpCode::unofficial(0x92, "JAM", 1, 2, AddressingMode::NoneAddressing),
+        #[cfg(feature = "illegal_opcodes")]
+        OpCode::unofficial(0xB2, "JAM", 1, 2, AddressingMode::NoneAddressing),
+        #[cfg(feature = "illegal_opcodes")]
+        OpCode::unofficial(0xD2, "JAM", 1, 2, AddressingMode::NoneAddressing),
+        #[cfg(feature = "illegal_opcodes")]
+        OpCode::unofficial(0xF2, "JAM", 1, 2, AddressingMode::NoneAddressing),
+
+        OpCode::new(0x4C, "JMP", 3, 3, AddressingMode::Absolute),
+        OpCode::new(0x6C, "JMP", 3, 5, AddressingMode::Indirect),
+
+        #[cfg(feature = "illegal_opcodes")]
+        OpCode::unofficial(0x8B, "ANE", 2, 2, AddressingMode::Immediate),
+
+        #[cfg(feature = "illegal_opcodes")]
+        OpCode::unofficial(0xA7, "LAX", 2, 3, AddressingMode::ZeroPage),
+        #[cfg(feature = "illegal_opcodes")]
+        OpCode::unofficial(0xB7, "LAX", 2, 4, AddressingMode::ZeroPageY),
+        #[cfg(feature = "illegal_opcodes")]
+        OpCode::unofficial(0xAF, "LAX", 3, 4, AddressingMode::Absolute),
+        #[cfg(feature = "illegal_opcodes")]
+        OpCode::unofficial(0xBF, "LAX", 3, 4 /* (+1 if page crossed) */, AddressingMode::AbsoluteY),
+        #[cfg(feature = "illegal_opcodes")]
+        OpCode::unofficial(0xA3, "LAX", 2, 6, AddressingMode::IndirectX),
+        #[cfg(feature = "illegal_opcodes")]
+        OpCode::unofficial(0xB3, "LAX", 2, 5 /* (+1 if page crossed) */, AddressingMode::IndirectY),
+
+        #[cfg(feature = "illegal_opcodes")]
+        OpCode::unofficial(0xAB, "LXA", 2, 2, AddressingMode::Immediate),
 
         OpCode::new(0xA9, "LDA", 2, 2, AddressingMode::Immediate),
         OpCode::new(0xA5, "LDA", 2, 3, AddressingMode::ZeroPage),
@@ -81,11 +190,26 @@ lazy_static! {
         OpCode::new(0xAC, "LDY", 3, 4, AddressingMode::Absolute),
         OpCode::new(0xBC, "LDY", 3, 4 /* (+1 if page crossed) */, AddressingMode::AbsoluteY),
 
+        OpCode::new(0x08, "PHP", 1, 3, AddressingMode::NoneAddressing),
+        OpCode::new(0x28, "PLP", 1, 4, AddressingMode::NoneAddressing),
+
+        OpCode::new(0xE9, "SBC", 2, 2, AddressingMode::Immediate),
+        OpCode::new(0xE5, "SBC", 2, 3, AddressingMode::ZeroPage),
+        OpCode::new(0xF5, "SBC", 2, 4, AddressingMode::ZeroPageX),
+        OpCode::new(0xED, "SBC", 3, 4, AddressingMode::Absolute),
+        OpCode::new(0xFD, "SBC", 3, 4 /* (+1 if page crossed) */, AddressingMode::AbsoluteX),
+        OpCode::new(0xF9, "SBC", 3, 4 /* (+1 if page crossed) */, AddressingMode::AbsoluteY),
+        OpCode::new(0xE1, "SBC", 2, 6, AddressingMode::IndirectX),
+        OpCode::new(0xF1, "SBC", 2, 5 /* (+1 if page crossed) */, AddressingMode::IndirectY),
+
+        OpCode::new(0x38, "SEC", 1, 2, AddressingMode::NoneAddressing),
+        OpCode::new(0xF8, "SED", 1, 2, AddressingMode::NoneAddressing),
+
         OpCode::new(0x85, "STA", 2, 3, AddressingMode::ZeroPage),
         OpCode::new(0x95, "STA", 2, 4, AddressingMode::ZeroPageX),
         OpCode::new(0x8D, "STA", 3, 4, AddressingMode::Absolute),
         OpCode::new(0x9D, "STA", 3, 5, AddressingMode::AbsoluteX),
-        OpCode::new(0x9D, "STA", 3, 5, AddressingMode::AbsoluteY),
+        OpCode::new(0x99, "STA", 3, 5, AddressingMode::AbsoluteY),
         OpCode::new(0x81, "STA", 2, 6, AddressingMode::IndirectX),
         OpCode::new(0x91, "STA", 2, 6, AddressingMode::IndirectY),
 
@@ -100,3 +224,14 @@ lazy_static! {
         map
     };
 }
+
+/**
+ * Look up the addressing mode of an opcode.
+ *
+ * @param code The opcode.
+ * @return The addressing mode, or `None` for a byte that is not a known
+ *         opcode.
+ */
+pub fn addressing_mode(code: u8) -> Option<&'static AddressingMode> {
+    CPU_OPCODES_MAP.get(&code).map(|op| &op.mode)
+}
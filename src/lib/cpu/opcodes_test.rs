@@ -0,0 +1,11 @@
+/**
+ * Unit tests for the opcode table.
+ */
+use super::*;
+
+#[test]
+fn test_addressing_mode_lookup() {
+    assert_eq!(addressing_mode(0xBD), Some(&AddressingMode::AbsoluteX));
+    assert_eq!(addressing_mode(0xA9), Some(&AddressingMode::Immediate));
+    assert_eq!(addressing_mode(0x03), None);
+}
@@ -0,0 +1,316 @@
+/*!
+ * Execution tracing for the 6502 CPU.
+ *
+ * Trace lines follow the layout of the well-known `nestest.log`, so that
+ * a run can be diffed against reference logs from other emulators.
+ */
+
+#[cfg(test)]
+#[path = "trace_test.rs"]
+mod trace_test;
+
+use crate::cpu::{AddressingMode, CPU, STACK};
+use crate::opcodes;
+
+// The mnemonic under which bytes that are not a known opcode are traced,
+// as in the disassembler.
+const DATA_BYTE: &str = ".byte";
+
+/**
+ * The contents of a trace line, for consumers that would rather not parse
+ * the formatted text.
+ *
+ * `operand` is the operand in assembler syntax together with its
+ * annotations, exactly as it appears in the trace line. A byte that is not
+ * a known opcode, which the CPU skips under `IllegalOpcodePolicy::Nop`, is
+ * described as `.byte $xx` data and is not official. There are no
+ * `scanline` or `dot` fields, as there is no PPU to take them from.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TraceRecord {
+    pub pc: u16,
+    pub opcode_bytes: Vec<u8>,
+    pub mnemonic: &'static str,
+    pub official: bool,
+    pub operand: String,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+    pub cycles: u64,
+}
+
+impl CPU {
+    /**
+     * Format the instruction pointed at by the program counter.
+     *
+     * The line is produced before the instruction executes and reads
+     * memory without modifying the CPU, e.g.:
+     *
+     * `8000  A5 10     LDA $10 = 55                    A:00 X:00 Y:00 P:20 SP:FD CYC:0`
+     *
+     * As in `nestest.log`, unofficial opcodes are marked with a `*` before
+     * the mnemonic.
+     *
+     * @return The trace line for the current instruction.
+     */
+    pub fn trace(&self) -> String {
+        let record = self.trace_record();
+
+        let bytes: Vec<String> = record
+            .opcode_bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+
+        let asm = if record.operand.is_empty() {
+            record.mnemonic.to_string()
+        } else {
+            format!("{} {}", record.mnemonic, record.operand)
+        };
+
+        format!(
+            "{:04X}  {:8} {}{:32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+            record.pc,
+            bytes.join(" "),
+            if record.official || record.mnemonic == DATA_BYTE {
+                ' '
+            } else {
+                '*'
+            },
+            asm,
+            record.a,
+            record.x,
+            record.y,
+            record.p,
+            record.sp,
+            record.cycles,
+        )
+    }
+
+    /**
+     * Describe the instruction pointed at by the program counter.
+     *
+     * This is the structured form of `trace()`, and likewise reads memory
+     * without modifying the CPU.
+     *
+     * @return The trace record for the current instruction.
+     */
+    pub fn trace_record(&self) -> TraceRecord {
+        let pc = self.program_counter;
+        let code = self.peek(pc);
+        let info = match opcodes::CPU_OPCODES_MAP.get(&code) {
+            Some(info) => info,
+            None => {
+                return TraceRecord {
+                    pc,
+                    opcode_bytes: vec![code],
+                    mnemonic: DATA_BYTE,
+                    official: false,
+                    operand: format!("${:02X}", code),
+                    a: self.register_a,
+                    x: self.register_x,
+                    y: self.register_y,
+                    p: self.p(),
+                    sp: self.stack_pointer,
+                    cycles: self.cycles,
+                }
+            }
+        };
+
+        TraceRecord {
+            pc,
+            opcode_bytes: (0..info.length as u16)
+                .map(|i| self.peek(pc.wrapping_add(i)))
+                .collect(),
+            mnemonic: info.instruction,
+            official: info.official,
+            operand: self.format_operand(info, pc.wrapping_add(1)),
+            a: self.register_a,
+            x: self.register_x,
+            y: self.register_y,
+            p: self.p(),
+            sp: self.stack_pointer,
+            cycles: self.cycles,
+        }
+    }
+
+    /**
+     * Describe the state of the CPU in a few lines of text, for pasting
+     * into bug reports, e.g.:
+     *
+     * ```text
+     * PC:8002 A:05 X:00 Y:00 SP:FB CYC:2
+     * P:24 nv-bdIzc
+     * Next: TAX
+     * Stack: 12 34
+     * ```
+     *
+     * Flags are shown in upper case when set. The stack lists up to four
+     * entries, starting from the top.
+     */
+    pub fn state_report(&self) -> String {
+        let flags: String = "NV-BDIZC"
+            .chars()
+            .enumerate()
+            .map(|(i, name)| {
+                if self.status & (0x80 >> i) != 0 {
+                    name
+                } else {
+                    name.to_ascii_lowercase()
+                }
+            })
+            .collect();
+
+        let stack: Vec<String> = (self.stack_pointer as u16 + 1..=0xFF)
+            .take(4)
+            .map(|offset| format!("{:02X}", self.peek(STACK + offset)))
+            .collect();
+
+        format!(
+            "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} SP:{:02X} CYC:{}\nP:{:02X} {}\nNext: {}\nStack: {}\n",
+            self.program_counter,
+            self.register_a,
+            self.register_x,
+            self.register_y,
+            self.stack_pointer,
+            self.cycles,
+            self.p(),
+            flags,
+            self.disassemble(self.program_counter).0,
+            if stack.is_empty() {
+                String::from("(empty)")
+            } else {
+                stack.join(" ")
+            },
+        )
+    }
+
+    /**
+     * Execute one instruction and report what was executed.
+     *
+     * @return The trace line captured before the instruction executed,
+     *         together with the number of cycles it took.
+     */
+    pub fn step_with_trace(&mut self) -> (String, u8) {
+        let line = self.trace();
+        let cycles = self.step();
+        (line, cycles)
+    }
+
+    /**
+     * Load and run a program, tracing every instruction, e.g. to compare
+     * against a golden log in a regression test.
+     *
+     * @param program          The program to run.
+     * @param max_instructions The most instructions to execute. Fewer are
+     *                         traced if the program halts first.
+     * @return The trace line of each instruction executed, in order.
+     */
+    pub fn load_and_trace(&mut self, program: Vec<u8>, max_instructions: usize) -> Vec<String> {
+        self.load(program);
+        self.reset();
+
+        let mut lines = Vec::new();
+        while !self.is_halted() && lines.len() < max_instructions {
+            lines.push(self.step_with_trace().0);
+        }
+        lines
+    }
+
+    /**
+     * Format an instruction's operand in assembler syntax, annotated with
+     * the effective address and the value stored there.
+     *
+     * @param info The opcode being formatted.
+     * @param pos  The position in memory of the instruction's operand.
+     */
+    fn format_operand(&self, info: &opcodes::OpCode, pos: u16) -> String {
+        let is_jump = matches!(info.instruction, "JMP" | "JSR");
+
+        match info.mode {
+            AddressingMode::NoneAddressing => String::new(),
+            AddressingMode::Accumulator => String::from("A"),
+            AddressingMode::Immediate => format!("#${:02X}", self.peek(pos)),
+
+            AddressingMode::ZeroPage => {
+                let addr = self.get_absolute_address(&info.mode, pos);
+                format!("${:02X} = {:02X}", addr, self.peek(addr))
+            }
+
+            AddressingMode::ZeroPageX | AddressingMode::ZeroPageY => {
+                let index = match info.mode {
+                    AddressingMode::ZeroPageX => 'X',
+                    _ => 'Y',
+                };
+                let addr = self.get_absolute_address(&info.mode, pos);
+                format!(
+                    "${:02X},{} @ {:02X} = {:02X}",
+                    self.peek(pos),
+                    index,
+                    addr,
+                    self.peek(addr)
+                )
+            }
+
+            AddressingMode::Absolute => {
+                let addr = self.get_absolute_address(&info.mode, pos);
+                if is_jump {
+                    format!("${:04X}", addr)
+                } else {
+                    format!("${:04X} = {:02X}", addr, self.peek(addr))
+                }
+            }
+
+            AddressingMode::AbsoluteX | AddressingMode::AbsoluteY => {
+                let index = match info.mode {
+                    AddressingMode::AbsoluteX => 'X',
+                    _ => 'Y',
+                };
+                let addr = self.get_absolute_address(&info.mode, pos);
+                format!(
+                    "${:04X},{} @ {:04X} = {:02X}",
+                    self.peek_u16(pos),
+                    index,
+                    addr,
+                    self.peek(addr)
+                )
+            }
+
+            AddressingMode::Relative => {
+                format!("${:04X}", self.get_absolute_address(&info.mode, pos))
+            }
+
+            AddressingMode::Indirect => {
+                let addr = self.get_absolute_address(&info.mode, pos);
+                format!("(${:04X}) = {:04X}", self.peek_u16(pos), addr)
+            }
+
+            AddressingMode::IndirectX => {
+                let base = self.peek(pos);
+                let ptr = base.wrapping_add(self.register_x);
+                let addr = self.get_absolute_address(&info.mode, pos);
+                format!(
+                    "(${:02X},X) @ {:02X} = {:04X} = {:02X}",
+                    base,
+                    ptr,
+                    addr,
+                    self.peek(addr)
+                )
+            }
+
+            AddressingMode::IndirectY => {
+                let base = self.peek(pos);
+                let addr = self.get_absolute_address(&info.mode, pos);
+                format!(
+                    "(${:02X}),Y = {:04X} @ {:04X} = {:02X}",
+                    base,
+                    self.peek_u16_zp_wrap(base),
+                    addr,
+                    self.peek(addr)
+                )
+            }
+        }
+    }
+}
@@ -0,0 +1,140 @@
+/**
+ * Unit tests for the CPU tracer.
+ */
+use super::TraceRecord;
+use crate::cpu::{Flag, IllegalOpcodePolicy, CPU};
+
+#[test]
+fn test_trace_immediate() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0xA9, 0x05, 0x00]);
+    cpu.reset();
+    assert_eq!(
+        cpu.trace(),
+        "8000  A9 05     LDA #$05                        A:00 X:00 Y:00 P:20 SP:FD CYC:0"
+    );
+}
+
+#[test]
+fn test_trace_zero_page_annotates_value() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0xA5, 0x10, 0x00]);
+    cpu.reset();
+    cpu.mem_write(0x10, 0x55);
+    assert_eq!(
+        cpu.trace(),
+        "8000  A5 10     LDA $10 = 55                    A:00 X:00 Y:00 P:20 SP:FD CYC:0"
+    );
+}
+
+#[test]
+#[cfg(feature = "illegal_opcodes")]
+fn test_trace_marks_unofficial_opcodes() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0xA7, 0x10, 0x00]);
+    cpu.reset();
+    cpu.mem_write(0x10, 0x55);
+    assert_eq!(
+        cpu.trace(),
+        "8000  A7 10    *LAX $10 = 55                    A:00 X:00 Y:00 P:20 SP:FD CYC:0"
+    );
+}
+
+#[test]
+fn test_step_with_trace_returns_line_and_cycles() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0xA9, 0x05, 0xAA, 0x00]);
+    cpu.reset();
+
+    let (line, cycles) = cpu.step_with_trace();
+    assert_eq!(
+        line,
+        "8000  A9 05     LDA #$05                        A:00 X:00 Y:00 P:20 SP:FD CYC:0"
+    );
+    assert_eq!(cycles, 2);
+    assert_eq!(cpu.register_a, 0x05);
+
+    let (line, _) = cpu.step_with_trace();
+    assert_eq!(
+        line,
+        "8002  AA        TAX                             A:05 X:00 Y:00 P:20 SP:FD CYC:2"
+    );
+}
+
+#[test]
+fn test_trace_record_fields() {
+    let mut cpu = CPU::new();
+    cpu.load(vec![0xBD, 0x00, 0x02, 0x00]);
+    cpu.reset();
+    cpu.register_x = 0x05;
+    cpu.mem_write(0x0205, 0x42);
+    assert_eq!(
+        cpu.trace_record(),
+        TraceRecord {
+            pc: 0x8000,
+            opcode_bytes: vec![0xBD, 0x00, 0x02],
+            mnemonic: "LDA",
+            official: true,
+            operand: String::from("$0200,X @ 0205 = 42"),
+            a: 0x00,
+            x: 0x05,
+            y: 0x00,
+            p: 0x20,
+            sp: 0xFD,
+            cycles: 0,
+        }
+    );
+}
+
+#[test]
+fn test_state_report() {
+    let mut cpu = CPU::new();
+    // LDA #$05; PHP; TAX
+    cpu.load(vec![0xA9, 0x05, 0x08, 0xAA, 0x00]);
+    cpu.reset();
+    cpu.step();
+    cpu.step();
+
+    assert_eq!(
+        cpu.state_report(),
+        "PC:8003 A:05 X:00 Y:00 SP:FC CYC:5\n\
+         P:20 nv-bdizc\n\
+         Next: TAX\n\
+         Stack: 30 00 00\n"
+    );
+
+    cpu.reset();
+    cpu.status = Flag::Carry.mask() | Flag::Negative.mask();
+    assert!(cpu.state_report().contains("P:A1 Nv-bdizC"));
+}
+
+#[test]
+fn test_load_and_trace() {
+    let mut cpu = CPU::new();
+    // LDA #$05; TAX; BRK
+    let program = vec![0xA9, 0x05, 0xAA, 0x00, 0x00];
+    assert_eq!(
+        cpu.load_and_trace(program.clone(), 100),
+        vec![
+            "8000  A9 05     LDA #$05                        A:00 X:00 Y:00 P:20 SP:FD CYC:0",
+            "8002  AA        TAX                             A:05 X:00 Y:00 P:20 SP:FD CYC:2",
+            "8003  00 00     BRK                             A:05 X:05 Y:00 P:20 SP:FD CYC:4",
+        ]
+    );
+    assert_eq!(cpu.load_and_trace(program, 2).len(), 2);
+}
+
+#[test]
+fn test_trace_unknown_opcode_under_nop_policy() {
+    let mut cpu = CPU::new();
+    cpu.set_illegal_opcode_policy(IllegalOpcodePolicy::Nop);
+    assert_eq!(
+        cpu.load_and_trace(vec![0xE8, 0x03, 0xE8, 0x00, 0x00], 10),
+        vec![
+            "8000  E8        INX                             A:00 X:00 Y:00 P:20 SP:FD CYC:0",
+            "8001  03        .byte $03                       A:00 X:01 Y:00 P:20 SP:FD CYC:2",
+            "8002  E8        INX                             A:00 X:01 Y:00 P:20 SP:FD CYC:4",
+            "8003  00 00     BRK                             A:00 X:02 Y:00 P:20 SP:FD CYC:6",
+        ]
+    );
+}